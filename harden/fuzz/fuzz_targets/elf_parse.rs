@@ -0,0 +1,23 @@
+//! Feed arbitrary bytes to the ELF parser and relocate what it accepts. Both may fail, but they must
+//! never panic.
+//!
+//! Run with `cargo fuzz run elf_parse` from the harden directory.
+
+#![no_main]
+
+#[macro_use]
+extern crate anyhow;
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/elf.rs"]
+mod elf;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut elf) = elf::Elf::parse(data) {
+        if elf.pie {
+            let _ = elf.relocate(0x10000);
+        }
+    }
+});
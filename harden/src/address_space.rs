@@ -1,10 +1,10 @@
-use anyhow::Error;
-use std::convert::TryInto;
+use anyhow::{Context, Error};
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 
 use crate::constants::PAGE_SIZE;
-use crate::elf::Elf;
 pub use crate::elf::Permissions;
+use crate::elf::{Elf, Segment};
 use crate::interval::Interval;
 use crate::phys_mem::{PhysMemory, PlaceAs};
 use crate::runtypes;
@@ -82,18 +82,38 @@ pub struct Mapping {
     vaddr: u64,
     perm: Permissions,
     backing: Backing,
+
+    /// What the mapping is for, e.g. "ELF segment 1" or "stack". This is used to explain
+    /// conflicting mappings.
+    origin: String,
 }
 
 impl fmt::Debug for Mapping {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad(&format!(
-            "< {:#08x} {:?}: {:#?}>",
-            self.vaddr, self.perm, self.backing
+            "< {:#08x} {:?}: {:#?} ({})>",
+            self.vaddr, self.perm, self.backing, self.origin
         ))
     }
 }
 
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ivl = self.virt_ivl();
+
+        f.pad(&format!("{} at {:#x}-{:#x}", self.origin, ivl.from, ivl.to))
+    }
+}
+
 impl Mapping {
+    /// Return the same mapping with a different description of what it is for.
+    pub fn with_origin(self, origin: &str) -> Self {
+        Mapping {
+            origin: origin.to_string(),
+            ..self
+        }
+    }
+
     /// Return a new mapping that is a page aligned version of self. Space that needs to be added is
     /// zero padded when required.
     pub fn page_aligned(&self) -> Self {
@@ -110,6 +130,7 @@ impl Mapping {
             vaddr: self.vaddr - offset,
             perm: self.perm,
             backing: self.backing.prepended(offset).extended(pad_bytes),
+            origin: self.origin.clone(),
         }
     }
 
@@ -127,13 +148,23 @@ impl From<&runtypes::VirtualMemoryRegion> for Mapping {
     fn from(mres: &runtypes::VirtualMemoryRegion) -> Self {
         Mapping {
             vaddr: mres.virt_start,
-            perm: Permissions::read_write(),
-            backing: match mres.phys {
-                runtypes::MemoryRegion::Phys { size, start } => Backing::Phys { phys: start, size },
+            perm: match mres.phys {
+                runtypes::MemoryRegion::ReadOnlyData { .. } => Permissions::read_only(),
+                _ => Permissions::read_write(),
+            },
+            backing: match &mres.phys {
+                runtypes::MemoryRegion::Phys { size, start } => Backing::Phys {
+                    phys: *start,
+                    size: *size,
+                },
                 runtypes::MemoryRegion::AnonymousZeroes { size } => Backing::InitializedData {
-                    data: vec![0; size.try_into().unwrap()],
+                    data: vec![0; (*size).try_into().unwrap()],
                 },
+                runtypes::MemoryRegion::ReadOnlyData { data } => {
+                    Backing::InitializedData { data: data.clone() }
+                }
             },
+            origin: "memory region".to_string(),
         }
     }
 }
@@ -144,25 +175,23 @@ pub struct AddressSpace {
     mappings: Vec<Mapping>,
 }
 
-impl From<&Elf> for AddressSpace {
+impl TryFrom<&Elf> for AddressSpace {
+    type Error = Error;
+
     /// Create an address space from an ELF binary. This converts all segments in the ELF to
     /// mappings. It ignores the physical memory addresses, so it might not be suitable for all
-    /// kinds of ELFs.
-    fn from(elf: &Elf) -> Self {
-        AddressSpace {
-            mappings: elf
-                .segments
+    /// kinds of ELFs. Fails if segments overlap after page alignment.
+    fn try_from(elf: &Elf) -> Result<Self, Self::Error> {
+        let mut addr_space = AddressSpace { mappings: vec![] };
+
+        addr_space.extend(
+            elf.segments
                 .iter()
-                .map(|s| Mapping {
-                    vaddr: s.vaddr,
-                    perm: s.permissions,
-                    backing: Backing::InitializedData {
-                        data: s.data.clone(),
-                    },
-                })
-                .map(|m| m.page_aligned())
-                .collect(),
-        }
+                .enumerate()
+                .map(|(i, s)| segment_mapping(i, s)),
+        )?;
+
+        Ok(addr_space)
     }
 }
 
@@ -175,9 +204,60 @@ impl IntoIterator for AddressSpace {
     }
 }
 
+/// Returns the page aligned mapping for an ELF segment.
+fn segment_mapping(index: usize, segment: &Segment) -> Mapping {
+    Mapping {
+        vaddr: segment.vaddr,
+        perm: segment.permissions,
+        backing: Backing::InitializedData {
+            data: segment.data.clone(),
+        },
+        origin: format!("ELF segment {}", index),
+    }
+    .page_aligned()
+}
+
 impl AddressSpace {
+    /// Create an address space from an ELF binary like `try_from`, but place all segments at their
+    /// physical addresses in `pmem` right away. Fails if the physical memory of a segment is not
+    /// available.
+    pub fn from_elf_at_paddr(elf: &Elf, pmem: &mut PhysMemory) -> Result<Self, Error> {
+        let mut addr_space = AddressSpace { mappings: vec![] };
+
+        for (i, s) in elf.segments.iter().enumerate() {
+            let mapping = segment_mapping(i, s);
+            let offset = s.vaddr - mapping.vaddr;
+
+            if s.paddr % PAGE_SIZE != offset {
+                return Err(format_err!(
+                    "ELF segment {} has paddr {:#x} and vaddr {:#x} at different page offsets",
+                    i,
+                    s.paddr,
+                    s.vaddr
+                ));
+            }
+
+            let phys = s.paddr - offset;
+
+            if let Backing::InitializedData { data } = &mapping.backing {
+                pmem.place_at(phys, data, mapping.perm)
+                    .with_context(|| format!("Failed to place ELF segment {} at {:#x}", i, phys))?;
+            }
+
+            addr_space.add(Mapping {
+                backing: Backing::Phys {
+                    size: mapping.size(),
+                    phys,
+                },
+                ..mapping
+            })?;
+        }
+
+        Ok(addr_space)
+    }
+
     /// Returns an iterator over all address space elements.
-    pub fn iter(&self) -> std::slice::Iter<Mapping> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Mapping> {
         self.mappings.iter()
     }
 
@@ -210,9 +290,54 @@ impl AddressSpace {
         self.lookup(vaddr).map(|(paddr, _)| paddr)
     }
 
-    /// Merge another address space into this one.
-    pub fn merge_from(&mut self, o: &AddressSpace) {
-        self.mappings.extend(o.iter().cloned());
+    /// Write data at a virtual address into the physical memory that backs it.
+    ///
+    /// The write is split at page boundaries, so it lands in the right place even if the virtual
+    /// range is backed by physically discontiguous pages. All touched memory must be fixated.
+    pub fn write(&self, pmem: &mut PhysMemory, vaddr: u64, data: &[u8]) -> Result<(), Error> {
+        let mut cur = vaddr;
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let page_left = PAGE_SIZE - (cur % PAGE_SIZE);
+            let len = std::cmp::min(page_left, rest.len().try_into()?);
+            let paddr = self.lookup_phys(cur).ok_or_else(|| {
+                format_err!(
+                    "Failed to write to vaddr {:#x}: no fixated mapping at {:#x}",
+                    vaddr,
+                    cur
+                )
+            })?;
+            let (chunk, remaining) = rest.split_at(len.try_into()?);
+
+            pmem.write(paddr, chunk);
+
+            cur += len;
+            rest = remaining;
+        }
+
+        Ok(())
+    }
+
+    /// Merge another address space into this one. Fails if any mappings overlap.
+    pub fn merge_from(&mut self, o: &AddressSpace) -> Result<(), Error> {
+        self.extend(o.iter().cloned())
+    }
+
+    /// Prefix the description of all mappings with the owner of the address space, e.g.
+    /// "kernel", to tell them apart after merging.
+    pub fn owned_by(self, owner: &str) -> AddressSpace {
+        AddressSpace {
+            mappings: self
+                .mappings
+                .into_iter()
+                .map(|m| {
+                    let origin = format!("{} {}", owner, m.origin);
+
+                    m.with_origin(&origin)
+                })
+                .collect(),
+        }
     }
 
     /// Fixate all initialized memory by writing it into the provided physical memory structure. Any
@@ -228,12 +353,13 @@ impl AddressSpace {
                             size: data.len().try_into()?,
                             phys: pmem
                                 .place(
-                                    &data,
+                                    data,
                                     if m.perm.write {
                                         PlaceAs::Unique
                                     } else {
                                         PlaceAs::Shareable
                                     },
+                                    m.perm,
                                 )
                                 .ok_or_else(|| {
                                     anyhow!(
@@ -242,7 +368,7 @@ impl AddressSpace {
                                 )
                                 })?,
                         },
-                        ..*m
+                        ..m.clone()
                     }),
                     Backing::Phys { .. } => Ok(m.clone()),
                 }
@@ -265,15 +391,27 @@ impl AddressSpace {
         Ok(copy)
     }
 
-    pub fn add(&mut self, mapping: Mapping) {
-        self.mappings.push(mapping)
+    /// Add a mapping. Fails if it overlaps an existing mapping.
+    pub fn add(&mut self, mapping: Mapping) -> Result<(), Error> {
+        if let Some(existing) = self
+            .mappings
+            .iter()
+            .find(|m| m.virt_ivl().intersects(mapping.virt_ivl()))
+        {
+            return Err(format_err!("{} overlaps {}", mapping, existing));
+        }
+
+        self.mappings.push(mapping);
+        Ok(())
     }
 
     /// Extend the address space with all mappings that the iterator produces.
-    pub fn extend<T: Iterator<Item = Mapping>>(&mut self, iter: T) {
+    pub fn extend<T: Iterator<Item = Mapping>>(&mut self, iter: T) -> Result<(), Error> {
         for m in iter {
-            self.add(m)
+            self.add(m)?
         }
+
+        Ok(())
     }
 }
 
@@ -305,6 +443,7 @@ mod tests {
             vaddr: 0xfff,
             perm: Permissions::read_write(),
             backing: Backing::InitializedData { data: vec![1, 2] },
+            origin: "test".to_string(),
         };
 
         let aligned = map.page_aligned();
@@ -323,4 +462,142 @@ mod tests {
             _ => panic!("page alignment changed backing store?"),
         }
     }
+
+    #[test]
+    fn test_from_elf_at_paddr() {
+        use crate::bump_ptr_alloc::BumpPointerAlloc;
+        use crate::elf::{ElfClass, Endianness};
+
+        let segment = |vaddr, paddr| Segment {
+            permissions: Permissions::read_only(),
+            vaddr,
+            paddr,
+            data: vec![1; 0x10],
+        };
+        let elf = |segments| Elf {
+            class: ElfClass::Class64,
+            endianness: Endianness::Little,
+            machine: crate::elf::EM_RISCV,
+            flags: 0,
+            entry: 0,
+            pie: false,
+            segments,
+            relocations: vec![],
+            tls: None,
+            symbols: Default::default(),
+        };
+        let pmem = || {
+            PhysMemory::new(
+                std::iter::once(BumpPointerAlloc::new(
+                    Interval::new_with_size(0x8000_0000, 0x10000),
+                    PAGE_SIZE,
+                ))
+                .collect(),
+            )
+        };
+
+        let mut p = pmem();
+        let aspace = AddressSpace::from_elf_at_paddr(
+            &elf(vec![
+                segment(0xffff_1010, 0x8000_2010),
+                segment(0xffff_3000, 0x8000_1000),
+            ]),
+            &mut p,
+        )
+        .unwrap();
+
+        assert_eq!(aspace.lookup_phys(0xffff_1010), Some(0x8000_2010));
+        assert_eq!(aspace.lookup_phys(0xffff_3000), Some(0x8000_1000));
+        assert_eq!(p.read(0x8000_2010, 1), vec![1]);
+
+        // Segments need to agree on the page offset and must not share physical memory.
+        assert!(AddressSpace::from_elf_at_paddr(
+            &elf(vec![segment(0xffff_1010, 0x8000_2000)]),
+            &mut pmem()
+        )
+        .is_err());
+        assert!(AddressSpace::from_elf_at_paddr(
+            &elf(vec![
+                segment(0xffff_1000, 0x8000_2000),
+                segment(0xffff_3000, 0x8000_2000)
+            ]),
+            &mut pmem()
+        )
+        .is_err());
+        assert!(AddressSpace::from_elf_at_paddr(
+            &elf(vec![segment(0xffff_1000, 0x9000_0000)]),
+            &mut pmem()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_write_discontiguous() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+        let mut aspace = AddressSpace { mappings: vec![] };
+
+        aspace
+            .add(Mapping {
+                vaddr: 0x1000,
+                perm: Permissions::read_write(),
+                backing: Backing::Phys {
+                    phys: 0x8000,
+                    size: 0x1000,
+                },
+                origin: "test".to_string(),
+            })
+            .unwrap();
+        aspace
+            .add(Mapping {
+                vaddr: 0x2000,
+                perm: Permissions::read_write(),
+                backing: Backing::Phys {
+                    phys: 0x4000,
+                    size: 0x1000,
+                },
+                origin: "test".to_string(),
+            })
+            .unwrap();
+
+        aspace.write(&mut pmem, 0x1ffe, &[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(pmem.read(0x8ffe, 2), vec![1, 2]);
+        assert_eq!(pmem.read(0x4000, 2), vec![3, 4]);
+        assert_eq!(pmem.read(0x9000, 2), vec![0, 0]);
+
+        assert!(aspace.write(&mut pmem, 0x2fff, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mapping = |vaddr, origin: &str| Mapping {
+            vaddr,
+            perm: Permissions::read_write(),
+            backing: Backing::Phys {
+                phys: 0x8000,
+                size: 0x2000,
+            },
+            origin: origin.to_string(),
+        };
+
+        let mut kernel = AddressSpace { mappings: vec![] };
+        kernel.add(mapping(0x8000_0000, "ELF segment 0")).unwrap();
+
+        let kernel = kernel.owned_by("kernel");
+
+        let mut user = AddressSpace { mappings: vec![] };
+        user.add(mapping(0x1000, "ELF segment 0")).unwrap();
+        user.add(mapping(0x3000, "stack")).unwrap();
+
+        assert_eq!(
+            user.add(mapping(0x2000, "heap")).unwrap_err().to_string(),
+            "heap at 0x2000-0x4000 overlaps ELF segment 0 at 0x1000-0x3000"
+        );
+
+        user.add(mapping(0x7fff_f000, "resource uart")).unwrap();
+        assert_eq!(
+            user.merge_from(&kernel).unwrap_err().to_string(),
+            "kernel ELF segment 0 at 0x80000000-0x80002000 overlaps resource uart at 0x7ffff000-0x80001000"
+        );
+    }
 }
@@ -1,18 +1,107 @@
 use anyhow::{Context, Error};
+use itertools::Itertools;
 use log::{debug, info};
-use std::io;
+use std::convert::{TryFrom, TryInto};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use crate::address_space::AddressSpace;
+use crate::address_space::{AddressSpace, Mapping, Permissions};
 use crate::bump_ptr_alloc::{BumpPointerAlloc, ChainedAlloc};
-use crate::constants::PAGE_SIZE;
-use crate::elf::{Elf, ElfClass};
+use crate::constants::{PAGE_SIZE, USER_LOAD_BASE};
+use crate::devicetree;
+use crate::elf::{self, Elf, ElfClass, Endianness, Symbol, SymbolType, TlsTemplate};
 use crate::elf_writer;
+use crate::fdt::Fdt;
+use crate::hex_writer;
 use crate::interval::Interval;
 use crate::page_table;
-use crate::phys_mem::PhysMemory;
+use crate::phys_mem::{PhysMemory, PlaceAs};
+use crate::raw_writer;
 use crate::runtypes;
-use crate::vec_utils::vec_u64_to_bytes;
+use crate::uboot_writer;
+
+/// The file formats a boot image can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// An ELF file with one segment per populated chunk of physical memory.
+    Elf,
+
+    /// A flat binary of physical memory with zero-filled gaps.
+    Binary,
+
+    IntelHex,
+
+    /// Motorola S-records.
+    Srec,
+
+    /// A legacy U-Boot image.
+    UImage,
+
+    /// A U-Boot Flattened Image Tree.
+    Fit,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        ImageFormat::Elf
+    }
+}
+
+static IMAGE_FORMAT_NAMES: [(&str, ImageFormat); 6] = [
+    ("elf", ImageFormat::Elf),
+    ("bin", ImageFormat::Binary),
+    ("ihex", ImageFormat::IntelHex),
+    ("srec", ImageFormat::Srec),
+    ("uimage", ImageFormat::UImage),
+    ("fit", ImageFormat::Fit),
+];
+
+impl ImageFormat {
+    /// Returns the names of all formats, e.g. for command line help.
+    pub fn names() -> Vec<&'static str> {
+        IMAGE_FORMAT_NAMES.iter().map(|&(k, _)| k).collect()
+    }
+
+    /// Returns true for formats that are not meant to be displayed.
+    pub fn is_binary(self) -> bool {
+        !matches!(self, ImageFormat::IntelHex | ImageFormat::Srec)
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(IMAGE_FORMAT_NAMES
+            .iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format_err!(
+                    "Unrecognized image format '{}', must be one of: {}",
+                    s,
+                    ImageFormat::names().join(" ")
+                )
+            })?
+            .1)
+    }
+}
+
+/// Options that influence what ends up in the boot image.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Synthesize a device tree for the system and patch its address into the kernel.
+    pub generate_dtb: bool,
+
+    /// Add section headers and symbols to ELF boot images for debugging.
+    pub debug_info: bool,
+
+    /// Place the kernel's segments at their physical addresses (`p_paddr`) instead of wherever
+    /// free memory is.
+    pub kernel_at_paddr: bool,
+
+    pub format: ImageFormat,
+}
 
 impl From<&runtypes::Configuration> for PhysMemory {
     fn from(system: &runtypes::Configuration) -> Self {
@@ -27,32 +116,77 @@ impl From<&runtypes::Configuration> for PhysMemory {
     }
 }
 
+/// Returns the anonymous memory mappings of a process with a description of what they are for.
+/// See `runtypes::Process::anon_mem` for their order.
+fn anon_mem_mappings(process: &runtypes::Process, is_kernel: bool) -> Vec<Mapping> {
+    process
+        .anon_mem
+        .iter()
+        .enumerate()
+        .map(|(i, vr)| {
+            let origin = match (is_kernel, i) {
+                (true, hart) => format!("stack of hart {}", hart),
+                (false, 0) => "stack".to_string(),
+                (false, 1) => "heap".to_string(),
+                (false, _) => "anonymous memory".to_string(),
+            };
+
+            Mapping::from(vr).with_origin(&origin)
+        })
+        .collect()
+}
+
+/// Returns the mappings of all resources of a process that need one.
+fn resource_mappings(process: &runtypes::Process) -> Vec<Mapping> {
+    process
+        .resources
+        .iter()
+        .filter_map(|(name, r)| {
+            r.opt_region
+                .as_ref()
+                .map(|vr| Mapping::from(vr).with_origin(&format!("resource {}", name)))
+        })
+        .collect()
+}
+
+/// Load the kernel and create its address space. With `at_paddr`, the kernel's segments are
+/// placed at their physical addresses in `pmem`, so this needs to happen before anything else is
+/// placed.
 fn to_kernel_as(
     process: &runtypes::Process,
     user_binaries: &Path,
+    at_paddr: bool,
+    pmem: &mut PhysMemory,
 ) -> Result<(Elf, AddressSpace), Error> {
     let kernel_path: PathBuf = [user_binaries, Path::new(&process.binary)].iter().collect();
     info!("Using {} as kernel binary", kernel_path.display(),);
 
     let kernel_elf = Elf::new(&kernel_path).context("Failed to load kernel ELF")?;
 
-    let mut kernel_as = AddressSpace::from(&kernel_elf);
-    kernel_as.extend(
-        process
-            .resources
-            .iter()
-            .filter_map(|(_, r)| r.opt_region.clone())
-            .map(|vr| (&vr).into()),
-    );
+    if kernel_elf.pie {
+        return Err(format_err!(
+            "The kernel is position-independent, but needs to be linked at a fixed address"
+        ));
+    }
 
-    Ok((kernel_elf, kernel_as))
+    let mut kernel_as = if at_paddr {
+        AddressSpace::from_elf_at_paddr(&kernel_elf, pmem)
+            .context("Failed to place kernel at its physical addresses")?
+    } else {
+        AddressSpace::try_from(&kernel_elf)?
+    };
+    kernel_as.extend(anon_mem_mappings(process, true).into_iter())?;
+    kernel_as.extend(resource_mappings(process).into_iter())?;
+
+    Ok((kernel_elf, kernel_as.owned_by("kernel")))
 }
 
 fn to_user_as(
     process: &runtypes::Process,
     user_binaries: &Path,
+    kernel_elf: &Elf,
     kernel_as: &AddressSpace,
-) -> Result<AddressSpace, Error> {
+) -> Result<(Elf, AddressSpace), Error> {
     let user_path: PathBuf = [user_binaries, Path::new(&process.binary)].iter().collect();
     info!(
         "Using {} as binary for process {}",
@@ -60,17 +194,43 @@ fn to_user_as(
         process.name
     );
 
-    let mut user_as = AddressSpace::from(&Elf::new(&user_path).context("Failed to load user ELF")?);
+    let mut user_elf = Elf::new(&user_path).context("Failed to load user ELF")?;
+
+    kernel_elf
+        .check_compatible(&user_elf)
+        .with_context(|| format!("{} does not match the kernel", user_path.display()))?;
+
+    if user_elf.pie {
+        let base = process.load_base.unwrap_or(USER_LOAD_BASE);
+
+        info!("Loading process {} at {:#x}", process.name, base);
+        user_elf
+            .relocate(base)
+            .with_context(|| format!("Failed to relocate {}", user_path.display()))?;
+    } else if let Some(base) = process.load_base {
+        return Err(format_err!(
+            "Load base {:#x} is configured, but {} is not position-independent",
+            base,
+            user_path.display()
+        ));
+    }
 
-    user_as.extend(process.anon_mem.iter().map(|vr| vr.into()));
+    let mut user_as = AddressSpace::try_from(&user_elf)?;
 
+    user_as.extend(anon_mem_mappings(process, false).into_iter())?;
     user_as.extend(
         process
-            .resources
+            .tls
             .iter()
-            .filter_map(|(_, r)| r.opt_region.clone())
-            .map(|vr| (&vr).into()),
-    );
+            .map(|vr| Mapping::from(vr).with_origin("thread-local storage")),
+    )?;
+    user_as.extend(
+        process
+            .config
+            .iter()
+            .map(|c| Mapping::from(&c.region).with_origin("configuration")),
+    )?;
+    user_as.extend(resource_mappings(process).into_iter())?;
 
     // Make mappings available at the user privilege.
     //
@@ -78,43 +238,296 @@ fn to_user_as(
     // mappings available to user code as well.
     user_as.make_user();
 
-    user_as.merge_from(kernel_as);
+    user_as.merge_from(kernel_as)?;
 
     debug!(
         "User address space for process {} is: {:#?}",
         process.name, user_as
     );
 
-    Ok(user_as)
+    Ok((user_elf, user_as))
+}
+
+/// Check that a TLS template fits into a TLS block of `size` bytes that starts at a page boundary.
+fn check_tls(template: &TlsTemplate, size: u64) -> Result<(), Error> {
+    let len: u64 = template.data.len().try_into()?;
+
+    if len > size {
+        Err(format_err!(
+            "TLS segment needs {:#x} bytes, but the TLS block only has {:#x} bytes (see tls_kb)",
+            len,
+            size
+        ))
+    } else if template.align > PAGE_SIZE {
+        Err(format_err!(
+            "TLS segment alignment {:#x} exceeds the page size",
+            template.align
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Initialize the TLS block of a process from the TLS template of its binary.
+///
+/// RISC-V places the TLS block directly at the thread pointer, so the template is copied to the
+/// start of the block. The rest of the block stays zero.
+fn init_tls(
+    pmem: &mut PhysMemory,
+    process: &runtypes::Process,
+    user_elf: &Elf,
+    user_as: &AddressSpace,
+) -> Result<(), Error> {
+    let template = match &user_elf.tls {
+        Some(template) => template,
+        None => return Ok(()),
+    };
+    let tls = process.tls.as_ref().ok_or_else(|| {
+        format_err!("Binary uses thread-local storage, but there is no TLS block")
+    })?;
+
+    check_tls(template, tls.size())?;
+    user_as.write(pmem, tls.virt_start, &template.data)
+}
+
+/// Check that all user binaries can run together with the kernel binary.
+pub fn check_binaries(system: &runtypes::Configuration, user_binaries: &Path) -> Result<(), Error> {
+    let binary_path = |process: &runtypes::Process| -> PathBuf {
+        [user_binaries, Path::new(&process.binary)].iter().collect()
+    };
+    let kernel_elf = Elf::new(&binary_path(&system.kernel)).context("Failed to load kernel ELF")?;
+
+    for process in system.processes.values() {
+        let path = binary_path(process);
+        let user_elf = Elf::new(&path)
+            .with_context(|| format!("Failed to load ELF of process {}", process.name))?;
+
+        kernel_elf.check_compatible(&user_elf).with_context(|| {
+            format!(
+                "{} of process {} does not match the kernel",
+                path.display(),
+                process.name
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Check whether `len` bytes of data can be patched into the given symbol.
+fn check_patchable(name: &str, sym: &Symbol, len: u64) -> Result<(), Error> {
+    if sym.r#type != SymbolType::Object {
+        Err(format_err!(
+            "Symbol '{}' is not a data object (type {:?})",
+            name,
+            sym.r#type
+        ))
+    } else if len > sym.size {
+        Err(format_err!(
+            "Patching {:#x} bytes into symbol '{}' exceeds its size of {:#x} bytes",
+            len,
+            name,
+            sym.size
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Serialize values as native machine words for the given ELF class and byte order.
+///
+/// This fails if a value does not fit into a 32-bit word on 32-bit targets.
+fn to_native_words(
+    class: ElfClass,
+    endianness: Endianness,
+    values: &[u64],
+) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+
+    for &v in values {
+        match (class, endianness) {
+            (ElfClass::Class32, _) => {
+                let v: u32 = v.try_into().map_err(|_| {
+                    format_err!("Value {:#x} does not fit into a 32-bit machine word", v)
+                })?;
+
+                out.extend_from_slice(&match endianness {
+                    Endianness::Little => v.to_le_bytes(),
+                    Endianness::Big => v.to_be_bytes(),
+                });
+            }
+            (ElfClass::Class64, Endianness::Little) => out.extend_from_slice(&v.to_le_bytes()),
+            (ElfClass::Class64, Endianness::Big) => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+
+    Ok(out)
 }
 
-/// Return the physical address of a symbol.
-fn sym_paddr(name: &str, elf: &Elf, addr_space: &AddressSpace) -> Result<u64, Error> {
-    let vaddr = elf
+/// Return the format of the page tables the kernel expects.
+///
+/// Page tables can only be generated for little-endian RISC-V so far.
+fn page_table_format(kernel_elf: &Elf) -> Result<page_table::Format, Error> {
+    if kernel_elf.machine != elf::EM_RISCV || kernel_elf.endianness != Endianness::Little {
+        return Err(format_err!(
+            "Page tables can only be generated for little-endian RISC-V, but the kernel is a {} {} ELF",
+            kernel_elf.endianness,
+            elf::machine_name(kernel_elf.machine)
+        ));
+    }
+
+    Ok(match kernel_elf.class {
+        ElfClass::Class32 => page_table::Format::RiscvSv32,
+        ElfClass::Class64 => page_table::Format::RiscvSv39,
+    })
+}
+
+/// Overwrite the content of a symbol in the kernel binary.
+///
+/// The symbol needs to be large enough to hold the data. The data is written via the kernel address
+/// space, so symbols that straddle physically discontiguous pages are handled correctly.
+fn patch_symbol(
+    pmem: &mut PhysMemory,
+    name: &str,
+    data: &[u8],
+    elf: &Elf,
+    addr_space: &AddressSpace,
+) -> Result<(), Error> {
+    let sym = elf
         .symbols
         .get(name)
-        .cloned()
         .ok_or_else(|| format_err!("Failed to look up virtual address of symbol '{}'", name))?;
 
-    addr_space.lookup_phys(vaddr).ok_or_else(|| {
-        format_err!(
-            "Failed to look up physical address of symbol '{}' (vaddr {:#x})",
-            name,
-            vaddr
+    check_patchable(name, sym, data.len().try_into()?)?;
+
+    addr_space
+        .write(pmem, sym.vaddr, data)
+        .with_context(|| format!("Failed to write symbol '{}'", name))
+}
+
+/// Collect the symbols for debugging the boot image.
+///
+/// These are the kernel's symbols and a marker for the entry point of each process, all at their
+/// physical addresses. Symbols that don't point into mapped memory are dropped.
+fn debug_symbols(
+    kernel_elf: &Elf,
+    kernel_as: &AddressSpace,
+    processes: &[(&runtypes::Process, &AddressSpace, u64)],
+) -> Vec<elf_writer::Symbol> {
+    let kernel_symbols = kernel_elf
+        .symbols
+        .iter()
+        .filter(|(name, sym)| {
+            !name.is_empty()
+                && matches!(
+                    sym.r#type,
+                    SymbolType::NoType | SymbolType::Object | SymbolType::Func
+                )
+        })
+        .filter_map(|(name, sym)| {
+            Some(elf_writer::Symbol {
+                name: name.clone(),
+                addr: kernel_as.lookup_phys(sym.vaddr)?,
+                size: sym.size,
+                r#type: sym.r#type,
+            })
+        });
+
+    let process_markers = processes.iter().filter_map(|(process, user_as, pc)| {
+        Some(elf_writer::Symbol {
+            name: format!("process.{}.entry", process.name),
+            addr: user_as.lookup_phys(*pc)?,
+            size: 0,
+            r#type: SymbolType::Func,
+        })
+    });
+
+    kernel_symbols.chain(process_markers).collect()
+}
+
+/// Place a device tree in physical memory and return its address.
+///
+/// Everything that is placed in physical memory so far and the device tree itself are marked as
+/// reserved, so the boot image is not mistaken for free memory.
+fn place_fdt(mut fdt: Fdt, pmem: &mut PhysMemory) -> Result<u64, Error> {
+    fdt.reserved = pmem
+        .chunks()
+        .iter()
+        .map(Interval::from)
+        .coalesce(|a, b| {
+            if a.joinable(b) {
+                Ok(a.hull(b))
+            } else {
+                Err((a, b))
+            }
+        })
+        .collect();
+
+    // The size of the device tree does not depend on the values in its reservation block, so we
+    // can place it before we know its final address.
+    fdt.reserved.push(Interval::default());
+
+    let size = u64::try_from(fdt.to_bytes().len())?;
+    let dtb = pmem
+        .place(
+            &vec![0; size.try_into()?],
+            PlaceAs::Unique,
+            Permissions::read_only(),
         )
-    })
+        .ok_or_else(|| format_err!("Failed to allocate memory for the device tree"))?;
+
+    *fdt.reserved.last_mut().unwrap() = Interval::new_with_size(dtb, size);
+    pmem.write(dtb, &fdt.to_bytes());
+
+    Ok(dtb)
 }
 
-fn process_entry(user_root: &Path, process: &runtypes::Process) -> Result<u64, Error> {
-    let binary_path: PathBuf = [user_root, Path::new(&process.binary)].iter().collect();
-    let elf = Elf::new(&binary_path).context("Failed to load process ELF")?;
+/// Place a device tree describing the system in physical memory and return its address.
+fn place_device_tree(
+    system: &runtypes::Configuration,
+    kernel_elf: &Elf,
+    pmem: &mut PhysMemory,
+) -> Result<u64, Error> {
+    // The synthesized CPU and interrupt controller nodes describe RISC-V harts.
+    if kernel_elf.machine != elf::EM_RISCV {
+        return Err(format_err!(
+            "Device trees can only be generated for RISC-V, but the kernel is a {} ELF",
+            elf::machine_name(kernel_elf.machine)
+        ));
+    }
 
-    Ok(elf.entry)
+    place_fdt(
+        devicetree::synthesize(
+            &system.name,
+            kernel_elf.class,
+            &system.available_memory,
+            system.harts,
+            &system.kernel.resources.values().collect::<Vec<_>>(),
+        )?,
+        pmem,
+    )
 }
 
-pub fn generate(system: &runtypes::Configuration, user_binaries: &Path) -> Result<(), Error> {
-    let (kernel_elf, mut kernel_as) = to_kernel_as(&system.kernel, user_binaries)?;
+/// Generate a boot image for the system and write it to `out`.
+pub fn generate(
+    system: &runtypes::Configuration,
+    user_binaries: &Path,
+    options: &Options,
+    mut out: &mut dyn Write,
+) -> Result<(), Error> {
+    if options.debug_info && options.format != ImageFormat::Elf {
+        return Err(format_err!(
+            "Debug information is only supported for ELF boot images"
+        ));
+    }
+
     let mut pmem: PhysMemory = system.into();
+    let (kernel_elf, mut kernel_as) = to_kernel_as(
+        &system.kernel,
+        user_binaries,
+        options.kernel_at_paddr,
+        &mut pmem,
+    )?;
 
     debug!("Kernel address space is: {:#?}", kernel_as);
 
@@ -123,80 +536,259 @@ pub fn generate(system: &runtypes::Configuration, user_binaries: &Path) -> Resul
     kernel_as.fixate(&mut pmem)?;
     debug!("Kernel address space fixated to: {:#?}", kernel_as);
 
-    let user_ass = system
+    let (user_elfs, user_ass): (Vec<Elf>, Vec<AddressSpace>) = system
         .processes
-        .iter()
-        .map(|(_, p)| -> Result<AddressSpace, Error> {
-            to_user_as(p, user_binaries, &kernel_as)?.fixated(&mut pmem)
+        .values()
+        .map(|p| -> Result<(Elf, AddressSpace), Error> {
+            let (user_elf, user_as) = to_user_as(p, user_binaries, &kernel_elf, &kernel_as)
+                .with_context(|| format!("Failed to set up address space of process {}", p.name))?;
+            let user_as = user_as.fixated(&mut pmem)?;
+
+            init_tls(&mut pmem, p, &user_elf, &user_as).with_context(|| {
+                format!(
+                    "Failed to set up thread-local storage of process {}",
+                    p.name
+                )
+            })?;
+
+            Ok((user_elf, user_as))
         })
-        .collect::<Result<Vec<AddressSpace>, Error>>()?;
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .unzip();
 
     info!("Generating page tables");
 
+    let pt_format = page_table_format(&kernel_elf)?;
     let user_satps = user_ass
         .iter()
-        .map(|a| {
-            let pt_format = match kernel_elf.class {
-                ElfClass::Class32 => page_table::Format::RiscvSv32,
-                ElfClass::Class64 => page_table::Format::RiscvSv39,
-            };
-            page_table::generate(pt_format, a, &mut pmem)
-        })
+        .map(|a| page_table::generate(pt_format, a, &mut pmem))
         .collect::<Result<Vec<u64>, Error>>()?;
 
-    let user_pcs = system
-        .processes
-        .iter()
-        .map(|(_, p)| -> Result<u64, Error> { process_entry(user_binaries, p) })
-        .collect::<Result<Vec<u64>, Error>>()?;
+    // Entry points of position-independent binaries are already relocated.
+    let user_pcs = user_elfs.iter().map(|e| e.entry).collect::<Vec<u64>>();
 
     info!("Patching kernel binary");
 
     // Patch the page table pointer the kernel boots with.
-    pmem.write(
-        sym_paddr("BOOT_SATP", &kernel_elf, &kernel_as).context("Failed to patch kernel SATP")?,
-        &vec_u64_to_bytes(&user_satps[0..1]),
-    );
+    patch_symbol(
+        &mut pmem,
+        "BOOT_SATP",
+        &to_native_words(kernel_elf.class, kernel_elf.endianness, &user_satps[0..1])?,
+        &kernel_elf,
+        &kernel_as,
+    )
+    .context("Failed to patch kernel SATP")?;
 
     // Patch the page tables of each user process.
-    pmem.write(
-        sym_paddr("USER_SATPS", &kernel_elf, &kernel_as)
-            .context("Failed to patch user process SATPs")?,
-        &vec_u64_to_bytes(&user_satps),
-    );
+    patch_symbol(
+        &mut pmem,
+        "USER_SATPS",
+        &to_native_words(kernel_elf.class, kernel_elf.endianness, &user_satps)?,
+        &kernel_elf,
+        &kernel_as,
+    )
+    .context("Failed to patch user process SATPs")?;
 
     // Patch thread entry points.
-    pmem.write(
-        sym_paddr("USER_PCS", &kernel_elf, &kernel_as)
-            .context("Failed to patch user process entry points")?,
-        &vec_u64_to_bytes(&user_pcs),
-    );
+    patch_symbol(
+        &mut pmem,
+        "USER_PCS",
+        &to_native_words(kernel_elf.class, kernel_elf.endianness, &user_pcs)?,
+        &kernel_elf,
+        &kernel_as,
+    )
+    .context("Failed to patch user process entry points")?;
+
+    if options.generate_dtb {
+        let dtb = place_device_tree(system, &kernel_elf, &mut pmem)
+            .context("Failed to generate device tree")?;
+
+        info!("Device tree is at {:#x}", dtb);
+
+        patch_symbol(
+            &mut pmem,
+            "BOOT_DTB",
+            &to_native_words(kernel_elf.class, kernel_elf.endianness, &[dtb])?,
+            &kernel_elf,
+            &kernel_as,
+        )
+        .context("Failed to patch device tree address")?;
+    }
 
     info!("Boot image needs {} KiB of RAM.", pmem.size() >> 10);
 
-    if atty::is(atty::Stream::Stdout) {
-        Err(format_err!(
-            "Refusing to write binary data to a terminal. Please redirect output to a stream."
-        ))
+    let entry = kernel_as
+        .lookup_phys(kernel_elf.entry)
+        .ok_or_else(|| format_err!("Failed to resolve vaddr {:#x}", kernel_elf.entry))?;
+
+    let debug_info = if options.debug_info {
+        let processes = system
+            .processes
+            .values()
+            .zip(user_ass.iter())
+            .zip(user_pcs.iter())
+            .map(|((p, a), &pc)| (p, a, pc))
+            .collect::<Vec<_>>();
+
+        Some(elf_writer::DebugInfo {
+            system_name: &system.name,
+            symbols: debug_symbols(&kernel_elf, &kernel_as, &processes),
+        })
     } else {
-        let stdout = io::stdout();
-        let out_buf = &mut stdout.lock();
+        None
+    };
 
-        info!("Serializing boot image");
+    info!("Serializing boot image");
 
-        elf_writer::write(
-            out_buf,
-            match kernel_elf.class {
-                ElfClass::Class32 => elf_writer::Format::Elf32,
-                ElfClass::Class64 => elf_writer::Format::Elf64,
-            },
-            kernel_as
-                .lookup_phys(kernel_elf.entry)
-                .ok_or_else(|| format_err!("Failed to resolve vaddr {:#x}", kernel_elf.entry))?,
+    match options.format {
+        ImageFormat::Elf => elf_writer::write(
+            &mut out,
+            elf_writer::Format::from(&kernel_elf),
+            entry,
             &pmem,
-        )?;
+            debug_info.as_ref(),
+        )?,
+        ImageFormat::Binary => {
+            let load_addr = raw_writer::write(&mut out, &pmem)?;
+
+            info!(
+                "Binary needs to be loaded at {:#x}. The entry point is at {:#x}.",
+                load_addr, entry
+            );
+        }
+        ImageFormat::IntelHex => {
+            hex_writer::write(&mut out, hex_writer::Format::IntelHex, entry, &pmem)?
+        }
+        ImageFormat::Srec => hex_writer::write(&mut out, hex_writer::Format::Srec, entry, &pmem)?,
+        ImageFormat::UImage | ImageFormat::Fit => {
+            let (load_addr, data) = raw_writer::flatten(&pmem)?;
+            let image = uboot_writer::Image {
+                name: &system.name,
+                load_addr,
+                entry,
+                data: &data,
+            };
 
-        info!("Finished writing boot image");
-        Ok(())
+            if options.format == ImageFormat::UImage {
+                uboot_writer::write_uimage(&mut out, &image)?
+            } else {
+                uboot_writer::write_fit(&mut out, &image)?
+            }
+        }
+    }
+
+    info!("Finished writing boot image");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdt::Node;
+
+    #[test]
+    fn test_place_fdt() {
+        let mut pmem = PhysMemory::new(
+            std::iter::once(BumpPointerAlloc::new(
+                Interval::new_with_size(0x1000, 0x10000),
+                0x1000,
+            ))
+            .collect(),
+        );
+
+        pmem.place_at(0x1000, &[1; 0x1000], Permissions::read_only())
+            .unwrap();
+        pmem.place_at(0x2000, &[2; 0x1000], Permissions::read_write())
+            .unwrap();
+
+        let fdt = Fdt {
+            boot_cpuid: 0,
+            reserved: vec![],
+            root: Node::new(""),
+        };
+        let dtb = place_fdt(fdt.clone(), &mut pmem).unwrap();
+        let size = u64::try_from(fdt.to_bytes().len()).unwrap() + 0x20;
+        let placed = Fdt::parse(&pmem.read(dtb, size)).unwrap();
+
+        assert_eq!(
+            placed.reserved,
+            vec![
+                Interval::new_with_size(0x1000, 0x2000),
+                Interval::new_with_size(dtb, size)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_patchable() {
+        let sym = Symbol {
+            vaddr: 0x1000,
+            size: 0x10,
+            r#type: SymbolType::Object,
+        };
+
+        assert!(check_patchable("sym", &sym, 0).is_ok());
+        assert!(check_patchable("sym", &sym, 0x10).is_ok());
+        assert!(check_patchable("sym", &sym, 0x11).is_err());
+
+        assert!(check_patchable(
+            "sym",
+            &Symbol {
+                r#type: SymbolType::Func,
+                ..sym
+            },
+            0x8
+        )
+        .is_err());
+        assert!(check_patchable("sym", &Symbol { size: 0, ..sym }, 0x8).is_err());
+    }
+
+    #[test]
+    fn test_check_tls() {
+        let template = TlsTemplate {
+            vaddr: 0x1000,
+            data: vec![0; 0x100],
+            align: 0x10,
+        };
+
+        assert!(check_tls(&template, 0x100).is_ok());
+        assert!(check_tls(&template, 0xff).is_err());
+        assert!(check_tls(
+            &TlsTemplate {
+                align: 2 * PAGE_SIZE,
+                ..template.clone()
+            },
+            PAGE_SIZE
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_image_format() {
+        assert_eq!("ELF".parse::<ImageFormat>().unwrap(), ImageFormat::Elf);
+        assert_eq!("srec".parse::<ImageFormat>().unwrap(), ImageFormat::Srec);
+        assert!("zimage".parse::<ImageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_to_native_words() {
+        assert_eq!(
+            to_native_words(ElfClass::Class32, Endianness::Little, &[0x8000_1234]).unwrap(),
+            vec![0x34, 0x12, 0x00, 0x80]
+        );
+        assert_eq!(
+            to_native_words(ElfClass::Class64, Endianness::Little, &[0x8000_1234]).unwrap(),
+            vec![0x34, 0x12, 0x00, 0x80, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            to_native_words(ElfClass::Class32, Endianness::Big, &[0x8000_1234]).unwrap(),
+            vec![0x80, 0x00, 0x12, 0x34]
+        );
+        assert_eq!(
+            to_native_words(ElfClass::Class64, Endianness::Big, &[0x8000_1234]).unwrap(),
+            vec![0, 0, 0, 0, 0x80, 0x00, 0x12, 0x34]
+        );
+        assert!(to_native_words(ElfClass::Class32, Endianness::Little, &[0x1_0000_0000]).is_err());
     }
 }
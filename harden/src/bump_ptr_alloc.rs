@@ -27,6 +27,34 @@ impl BumpPointerAlloc {
 
         BumpPointerAlloc { free, min_align }
     }
+
+    /// Take `ivl` out of the free space. Returns allocators for the free space below and above it
+    /// in address order or None, if `ivl` is not completely free.
+    fn split_at(&self, ivl: Interval) -> Option<Vec<BumpPointerAlloc>> {
+        assert_eq!(ivl.from & (self.min_align - 1), 0);
+        assert_eq!(ivl.to & (self.min_align - 1), 0);
+
+        if ivl.empty() || ivl.from < self.free.from || ivl.to > self.free.to {
+            return None;
+        }
+
+        Some(
+            [
+                Interval {
+                    from: self.free.from,
+                    to: ivl.from,
+                },
+                Interval {
+                    from: ivl.to,
+                    to: self.free.to,
+                },
+            ]
+            .iter()
+            .filter(|i| !i.empty())
+            .map(|&i| BumpPointerAlloc::new(i, self.min_align))
+            .collect(),
+        )
+    }
 }
 
 impl SimpleAlloc for BumpPointerAlloc {
@@ -74,6 +102,26 @@ impl<T: SimpleAlloc> FromIterator<T> for ChainedAlloc<T> {
     }
 }
 
+impl ChainedAlloc<BumpPointerAlloc> {
+    /// Reserve a specific range, so it is never handed out by `alloc`. The range needs to be aligned
+    /// to the alignment of the allocators. Returns false, if the range is not completely free.
+    pub fn reserve(&mut self, ivl: Interval) -> bool {
+        match self
+            .backends
+            .iter()
+            .enumerate()
+            .find_map(|(i, b)| Some((i, b.split_at(ivl)?)))
+        {
+            Some((i, parts)) => {
+                // Backends are stored in reverse order.
+                self.backends.splice(i..=i, parts.into_iter().rev());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl<T: SimpleAlloc> SimpleAlloc for ChainedAlloc<T> {
     fn alloc(&mut self, size: u64) -> std::option::Option<u64> {
         match self.backends.last_mut() {
@@ -118,4 +166,26 @@ mod tests {
         assert_eq!(a.alloc(0x20), Some(0x2000));
         assert_eq!(a.alloc(0x20), None);
     }
+
+    #[test]
+    fn test_reserve() {
+        let mut a = vec![
+            BumpPointerAlloc::new(Interval::new_with_size(0x1000, 0x40), 0x10),
+            BumpPointerAlloc::new(Interval::new_with_size(0x2000, 0x40), 0x10),
+        ]
+        .into_iter()
+        .collect::<ChainedAlloc<_>>();
+
+        assert!(a.reserve(Interval::new_with_size(0x1010, 0x10)));
+        assert!(a.reserve(Interval::new_with_size(0x2000, 0x40)));
+
+        // Reserved ranges cannot be reserved again or straddle allocators.
+        assert!(!a.reserve(Interval::new_with_size(0x1010, 0x10)));
+        assert!(!a.reserve(Interval::new_with_size(0x1030, 0x20)));
+
+        assert_eq!(a.alloc(0x10), Some(0x1000));
+        assert_eq!(a.alloc(0x10), Some(0x1020));
+        assert_eq!(a.alloc(0x10), Some(0x1030));
+        assert_eq!(a.alloc(0x10), None);
+    }
 }
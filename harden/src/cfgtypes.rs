@@ -2,13 +2,79 @@
 //! usually not meant to be modified.
 
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 use crate::framebuffer;
 
+/// The initial value of an argument register of a process' thread.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    /// A constant value.
+    Value(u64),
+    /// The index of the process in the kernel's process list.
+    ProcessIndex,
+    /// The first address of the process heap.
+    HeapStart,
+    /// The first address after the process heap.
+    HeapEnd,
+    /// The address of the instance configuration.
+    ConfigBlob,
+}
+
+/// A scalar value in an instance configuration.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    Natural(u64),
+    Integer(i64),
+    Bool(bool),
+    Text(String),
+}
+
+/// The configuration record of a process instance. In Dhall, this is written as `toMap` of a
+/// record of `ConfigValue`s.
+pub type Config = BTreeMap<String, ConfigValue>;
+
+/// A CPU budget that is replenished every period. All values are in timer ticks.
+///
+/// Budgets are advisory. The schedulability analysis relies on them, but the kernel does not
+/// enforce them.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Budget {
+    pub budget: u64,
+    pub period: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Scheduling {
+    /// The priority of the process' thread. Higher values are more important.
+    pub priority: u64,
+
+    /// The time slice in timer ticks. If this is not set, a default is derived from the timer
+    /// frequency.
+    pub time_slice: Option<u64>,
+
+    pub budget: Option<Budget>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Process {
     pub name: String,
     pub program: String,
+
+    /// Initial values of the argument registers starting from a0.
+    pub args: Vec<Argument>,
+
+    /// The optional configuration record of this process instance.
+    pub config: Option<Config>,
+
+    pub sched: Scheduling,
+
+    /// The hart this process is pinned to.
+    pub hart: u64,
+
+    /// The address a position-independent binary is loaded at. If this is not set, a default is
+    /// used.
+    pub load_base: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -24,6 +90,9 @@ pub struct System {
     pub kernel: String,
     pub processes: Vec<Process>,
     pub mappings: Vec<Mapping>,
+
+    /// Harts that are intentionally left without threads.
+    pub idle_harts: Vec<u64>,
 }
 
 // TODO Use Interval for this.
@@ -73,12 +142,18 @@ pub struct NamedResource {
 #[derive(Deserialize, Debug, Clone)]
 pub struct NamedResourceType {
     pub name: String,
+    #[allow(dead_code)]
     pub r#type: ResourceType,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Machine {
+    #[allow(dead_code)]
     pub name: String,
+
+    /// The number of harts (hardware threads) of the machine.
+    pub harts: u64,
+
     pub available_memory: Vec<MemoryRegion>,
     pub devices: Vec<NamedResource>,
 }
@@ -87,5 +162,12 @@ pub struct Machine {
 pub struct Application {
     pub name: String,
     pub heap_kb: u64,
+
+    /// The stack size in KiB. For the kernel, this is the size of the stack of each hart.
+    pub stack_kb: u64,
+
+    /// The size of the thread-local storage block of each thread in KiB. The TLS template of the
+    /// binary has to fit into it. The kernel has no TLS.
+    pub tls_kb: u64,
     pub needs: Vec<NamedResourceType>,
 }
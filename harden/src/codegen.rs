@@ -4,6 +4,7 @@ use itertools::Itertools;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
+use crate::config_blob;
 use crate::framebuffer;
 use crate::runtypes;
 
@@ -11,10 +12,10 @@ use crate::runtypes;
 #[derive(Copy, Clone)]
 pub enum Language {
     /// C++
-    CPP,
+    Cpp,
 }
 
-static LANGUAGE_NAMES: [(&'static str, Language); 1] = [("c++", Language::CPP)];
+static LANGUAGE_NAMES: [(&str, Language); 1] = [("c++", Language::Cpp)];
 
 /// The error that is returned for failure to parse a string into `Language`.
 #[derive(Debug)]
@@ -112,9 +113,20 @@ inline uint32_t volatile * const {}_reg {{reinterpret_cast<uint32_t volatile *>(
     }
 }
 
+fn generate_cpp_config(config: &runtypes::InstanceConfig) -> String {
+    format!(
+        "
+{}
+inline instance_config_t const &instance_config {{*reinterpret_cast<instance_config_t const *>({:#x}ul)}};
+",
+        config_blob::cpp_declaration("instance_config_t", &config.value),
+        config.region.virt_start
+    )
+}
+
 pub fn generate(language: Language, process: &runtypes::Process) -> String {
     match language {
-        Language::CPP => format!(
+        Language::Cpp => format!(
             "// Automatically generated. Do not touch.
 
 #pragma once
@@ -131,6 +143,7 @@ pub fn generate(language: Language, process: &runtypes::Process) -> String {
                 .resources
                 .iter()
                 .map(|(name, res)| generate_cpp_res(name, res))
+                .chain(process.config.iter().map(generate_cpp_config))
                 .join("\n")
         ),
     }
@@ -0,0 +1,116 @@
+//! Serialize per-instance configuration records into memory blobs.
+//!
+//! Each process instance can carry a flat record of scalar values that is placed into a read-only
+//! page of the process. The layout is simple enough to be described by a plain C++ struct:
+//!
+//! - `Natural` and `Bool` become `uint64_t`, `Integer` becomes `int64_t`,
+//! - `Text` becomes a zero-terminated `char` array padded to a multiple of 8 bytes.
+//!
+//! Fields are laid out in alphabetical order. As every field is a multiple of 8 bytes in size, there
+//! is no padding between them.
+
+use anyhow::Error;
+use itertools::Itertools;
+
+use crate::cfgtypes::{Config, ConfigValue};
+
+/// The number of bytes a `Text` value occupies including its terminating zero.
+fn text_len(s: &str) -> usize {
+    (s.len() + 1 + 7) & !7
+}
+
+/// Serialize a configuration record into its binary representation.
+pub fn serialize(config: &Config) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+
+    for (name, value) in config {
+        match value {
+            ConfigValue::Bool(b) => out.extend_from_slice(&u64::from(*b).to_le_bytes()),
+            ConfigValue::Natural(n) => out.extend_from_slice(&n.to_le_bytes()),
+            ConfigValue::Integer(i) => out.extend_from_slice(&i.to_le_bytes()),
+            ConfigValue::Text(s) => {
+                if s.contains('\0') {
+                    return Err(format_err!(
+                        "Configuration field '{}' contains a zero byte",
+                        name
+                    ));
+                }
+
+                let mut bytes = s.as_bytes().to_vec();
+
+                bytes.resize(text_len(s), 0);
+                out.extend(bytes);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn cpp_member(name: &str, value: &ConfigValue) -> String {
+    match value {
+        ConfigValue::Bool(_) | ConfigValue::Natural(_) => format!("uint64_t {};", name),
+        ConfigValue::Integer(_) => format!("int64_t {};", name),
+        ConfigValue::Text(s) => format!("char {}[{}];", name, text_len(s)),
+    }
+}
+
+/// Generate a C++ struct declaration that matches the layout produced by `serialize`.
+pub fn cpp_declaration(type_name: &str, config: &Config) -> String {
+    format!(
+        "struct {} {{\n{}\n}};",
+        type_name,
+        config
+            .iter()
+            .map(|(name, value)| format!("  {}", cpp_member(name, value)))
+            .join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Config {
+        [
+            ("name", ConfigValue::Text("h1".to_string())),
+            ("id", ConfigValue::Natural(7)),
+            ("flag", ConfigValue::Bool(true)),
+            ("off", ConfigValue::Integer(-1)),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect()
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            serialize(&example()).unwrap(),
+            [
+                &1u64.to_le_bytes()[..],
+                &7u64.to_le_bytes()[..],
+                b"h1\0\0\0\0\0\0",
+                &(-1i64).to_le_bytes()[..]
+            ]
+            .concat()
+        );
+
+        let mut bad = example();
+        bad.insert("bad".to_string(), ConfigValue::Text("a\0b".to_string()));
+        assert!(serialize(&bad).is_err());
+    }
+
+    #[test]
+    fn test_cpp_declaration() {
+        assert_eq!(
+            cpp_declaration("config_t", &example()),
+            "struct config_t {
+  uint64_t flag;
+  uint64_t id;
+  char name[8];
+  int64_t off;
+};"
+        );
+    }
+}
@@ -7,14 +7,28 @@ pub const USER_RESOURCE_START: u64 = 0x40000000;
 /// The end of the resource area in processes.
 pub const USER_RESOURCE_END: u64 = 0x50000000;
 
-/// The default stack size for user programs.
-pub const USER_STACK_SIZE: u64 = 0x4000;
-
 /// The virtual address where kernel resource mappings start.
 pub const KERN_RESOURCE_START: u64 = 0x88000000;
 
 /// The end of the resource area in the kernel.
 pub const KERN_RESOURCE_END: u64 = 0x90000000;
 
+/// The address position-independent user binaries are loaded at, unless a process instance
+/// specifies its own load base. This is where static binaries are usually linked as well.
+pub const USER_LOAD_BASE: u64 = 0x10000;
+
 /// The default page size.
 pub const PAGE_SIZE: u64 = 0x1000;
+
+/// The number of argument registers (a0-a7) that can be initialized for user threads.
+pub const ARG_REGISTERS: usize = 8;
+
+/// The scheduling frequency that determines the time slice of threads that don't specify one.
+pub const DEFAULT_SCHEDULE_HZ: u64 = 128;
+
+/// The highest scheduling frequency we allow. This bounds how short time slices can be.
+pub const MAX_SCHEDULE_HZ: u64 = 10000;
+
+/// The number of harts the kernel can run on. The kernel has no SMP support yet, so it only boots
+/// on a single hart.
+pub const MAX_HARTS: u64 = 1;
@@ -0,0 +1,878 @@
+//! Derive machine descriptions from device trees.
+//!
+//! The machine files in `machines/` describe the same hardware as the device tree that firmware
+//! or QEMU hand to the operating system. Instead of copying addresses by hand, a first version of
+//! a machine file can be generated from a DTB. We recognize memory, the SiFive PLIC, the timebase
+//! frequency that is used by the SBI timer and the devices we have drivers for. Everything else is
+//! ignored.
+//!
+//! The other direction is checking a hand-written machine description against the device tree of
+//! the actual hardware, so wrong addresses are caught before we build an image.
+//!
+//! Finally, we can synthesize a device tree for the booted system for firmware that expects one.
+//! It only contains the configured memory, the harts and the devices that the kernel uses.
+
+use anyhow::Error;
+use itertools::Itertools;
+use log::warn;
+use std::convert::TryInto;
+
+use crate::cfgtypes::{Machine, MemoryRegion, NamedResource, Resource, ResourceType};
+use crate::constants::PAGE_SIZE;
+use crate::elf::ElfClass;
+use crate::fdt::{self, Fdt, Node, NodeRef};
+use crate::framebuffer;
+use crate::interval::Interval;
+use crate::runtypes;
+
+/// Remove `hole` from all regions in `regions`.
+fn subtract(regions: &[Interval], hole: Interval) -> Vec<Interval> {
+    regions
+        .iter()
+        .flat_map(|&r| {
+            if r.intersects(hole) {
+                vec![
+                    Interval {
+                        from: r.from,
+                        to: hole.from,
+                    },
+                    Interval {
+                        from: hole.to,
+                        to: r.to,
+                    },
+                ]
+            } else {
+                vec![r]
+            }
+        })
+        .filter(|r| !r.empty())
+        .collect()
+}
+
+/// Returns the memory regions of all given nodes.
+fn regions<'a>(nodes: impl Iterator<Item = &'a NodeRef<'a>>) -> Result<Vec<Interval>, Error> {
+    Ok(nodes
+        .map(|n| n.reg())
+        .collect::<Result<Vec<Vec<Interval>>, Error>>()?
+        .concat())
+}
+
+/// Returns the only region of a device.
+fn single_region(node: &NodeRef) -> Result<MemoryRegion, Error> {
+    match node.reg()?.as_slice() {
+        [r] => Ok(MemoryRegion {
+            start: r.from,
+            size: r.size(),
+        }),
+        _ => Err(format_err!(
+            "Device {} needs exactly one memory region",
+            node.node.name
+        )),
+    }
+}
+
+fn to_pixel_format(format: &str) -> Option<framebuffer::PixelFormat> {
+    match format {
+        "r5g6b5" => Some(framebuffer::PixelFormat::R5G6B5),
+        _ => None,
+    }
+}
+
+fn pixel_format_name(pixel: framebuffer::PixelFormat) -> &'static str {
+    match pixel {
+        framebuffer::PixelFormat::R5G6B5 => "r5g6b5",
+    }
+}
+
+/// Returns the compatible strings of device tree nodes that can serve as the given resource.
+fn compatible(rtype: ResourceType) -> &'static [&'static str] {
+    match rtype {
+        ResourceType::Framebuffer => &["simple-framebuffer"],
+        ResourceType::SiFivePLIC => &["sifive,plic-1.0.0", "riscv,plic0"],
+        ResourceType::SBITimer => &[],
+        ResourceType::SpinalGPIO => &["spinal-lib,gpio-1.0"],
+    }
+}
+
+fn is_compatible(node: &NodeRef, rtype: ResourceType) -> bool {
+    compatible(rtype).iter().any(|c| node.node.is_compatible(c))
+}
+
+fn resource_type(resource: &Resource) -> ResourceType {
+    match resource {
+        Resource::Framebuffer { .. } => ResourceType::Framebuffer,
+        Resource::SiFivePLIC { .. } => ResourceType::SiFivePLIC,
+        Resource::SBITimer { .. } => ResourceType::SBITimer,
+        Resource::SpinalGPIO { .. } => ResourceType::SpinalGPIO,
+    }
+}
+
+/// Returns the MMIO region of a resource.
+fn resource_region(resource: &Resource) -> Option<&MemoryRegion> {
+    match resource {
+        Resource::Framebuffer { region, .. }
+        | Resource::SiFivePLIC { region, .. }
+        | Resource::SpinalGPIO { region, .. } => Some(region),
+        Resource::SBITimer { .. } => None,
+    }
+}
+
+/// Convert a device tree node into a resource, if it is a device we know.
+fn to_resource(node: &NodeRef) -> Result<Option<Resource>, Error> {
+    let n = node.node;
+    let property = |name: &str| {
+        n.u32(name)
+            .ok_or_else(|| format_err!("Device {} lacks the numeric property '{}'", n.name, name))
+    };
+
+    Ok(if is_compatible(node, ResourceType::SiFivePLIC) {
+        Some(Resource::SiFivePLIC {
+            ndev: property("riscv,ndev")?.try_into()?,
+            region: single_region(node)?,
+        })
+    } else if is_compatible(node, ResourceType::SpinalGPIO) {
+        Some(Resource::SpinalGPIO {
+            ngpio: property("ngpio")?.try_into()?,
+            region: single_region(node)?,
+        })
+    } else if is_compatible(node, ResourceType::Framebuffer) {
+        let format = n.string("format").unwrap_or("<none>");
+
+        match to_pixel_format(format) {
+            Some(pixel) => Some(Resource::Framebuffer {
+                format: framebuffer::Format {
+                    height: property("height")?,
+                    width: property("width")?,
+                    stride: property("stride")?,
+                    pixel,
+                },
+                region: single_region(node)?,
+            }),
+            None => {
+                warn!(
+                    "Ignoring framebuffer {} with unsupported format {}",
+                    n.name, format
+                );
+                None
+            }
+        }
+    } else {
+        None
+    })
+}
+
+/// Returns the name under which a resource appears in the machine description.
+fn resource_name(node: &NodeRef, resource: &Resource) -> String {
+    match resource {
+        Resource::SiFivePLIC { .. } => "plic".to_string(),
+        _ => node.node.base_name().to_string(),
+    }
+}
+
+/// Returns the regions of all devices that are backed by RAM.
+fn device_region(resource: &Resource) -> Option<Interval> {
+    match resource {
+        Resource::Framebuffer { region, .. } => {
+            Some(Interval::new_with_size(region.start, region.size))
+        }
+        _ => None,
+    }
+}
+
+fn cpus(fdt: &Fdt) -> Result<&Node, Error> {
+    fdt.find("/cpus")
+        .ok_or_else(|| format_err!("Device tree has no /cpus node"))
+}
+
+/// Returns the frequency of the timer that is used by SBI.
+fn timebase_frequency(fdt: &Fdt) -> Result<u64, Error> {
+    let cpus = cpus(fdt)?;
+
+    // The timebase frequency may also be specified per CPU. We don't support different
+    // frequencies, so we take the first one we find.
+    std::iter::once(cpus)
+        .chain(cpus.children.iter())
+        .find_map(|n| n.u64("timebase-frequency"))
+        .ok_or_else(|| format_err!("Device tree has no timebase-frequency"))
+}
+
+/// Returns all RAM described by memory nodes.
+fn memory_regions(nodes: &[NodeRef]) -> Result<Vec<Interval>, Error> {
+    regions(
+        nodes
+            .iter()
+            .filter(|n| n.node.is_enabled() && n.node.string("device_type") == Some("memory")),
+    )
+}
+
+/// Returns all memory that is reserved by firmware.
+fn reserved_regions(fdt: &Fdt, nodes: &[NodeRef]) -> Result<Vec<Interval>, Error> {
+    let reserved_memory: Vec<Interval> = match fdt.find("/reserved-memory") {
+        Some(parent) => regions(
+            nodes
+                .iter()
+                .filter(|n| parent.children.iter().any(|c| std::ptr::eq(c, n.node))),
+        )?,
+        None => vec![],
+    };
+
+    Ok(fdt
+        .reserved
+        .iter()
+        .cloned()
+        .chain(reserved_memory)
+        .collect())
+}
+
+/// Build a machine description from a device tree.
+pub fn import_machine(name: &str, fdt: &Fdt) -> Result<Machine, Error> {
+    let nodes = fdt.nodes()?;
+
+    let harts = cpus(fdt)?
+        .children
+        .iter()
+        .filter(|c| c.string("device_type") == Some("cpu") && c.is_enabled())
+        .count();
+
+    let mut devices: Vec<NamedResource> = vec![];
+
+    for node in nodes.iter().filter(|n| n.node.is_enabled()) {
+        if let Some(resource) = to_resource(node)? {
+            let mut name = resource_name(node, &resource);
+
+            // Disambiguate multiple instances of the same device by their unit address.
+            if devices.iter().any(|d| d.name == name) {
+                name = node.node.name.replace('@', "-");
+            }
+
+            devices.push(NamedResource { name, resource });
+        }
+    }
+
+    devices.push(NamedResource {
+        name: "sbitimer".to_string(),
+        resource: Resource::SBITimer {
+            freq_hz: timebase_frequency(fdt)?,
+        },
+    });
+
+    let available_memory = reserved_regions(fdt, &nodes)?
+        .into_iter()
+        .chain(devices.iter().filter_map(|d| device_region(&d.resource)))
+        .fold(memory_regions(&nodes)?, |regions, hole| {
+            subtract(&regions, hole)
+        })
+        .into_iter()
+        .map(|r| Interval {
+            from: (r.from + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE,
+            to: r.to / PAGE_SIZE * PAGE_SIZE,
+        })
+        .filter(|r| !r.empty())
+        .map(|r| MemoryRegion {
+            start: r.from,
+            size: r.size(),
+        })
+        .collect();
+
+    Ok(Machine {
+        name: name.to_string(),
+        harts: harts.try_into()?,
+        available_memory,
+        devices,
+    })
+}
+
+/// Check that a region of available memory is RAM that is not reserved.
+fn check_memory(
+    region: &MemoryRegion,
+    memory: &[Interval],
+    reserved: &[Interval],
+) -> Result<(), Error> {
+    let r = Interval::new_with_size(region.start, region.size);
+
+    if !memory.iter().any(|m| m.intersection(r) == r) {
+        return Err(format_err!(
+            "Available memory {:#x}-{:#x} is not covered by a memory node",
+            r.from,
+            r.to
+        ));
+    }
+
+    if let Some(res) = reserved.iter().find(|res| res.intersects(r)) {
+        return Err(format_err!(
+            "Available memory {:#x}-{:#x} overlaps reserved memory {:#x}-{:#x}",
+            r.from,
+            r.to,
+            res.from,
+            res.to
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that a device exists in the device tree at the configured address.
+fn check_device(device: &NamedResource, nodes: &[NodeRef], fdt: &Fdt) -> Result<(), Error> {
+    let rtype = resource_type(&device.resource);
+
+    let region = match resource_region(&device.resource) {
+        Some(region) => region,
+        None => {
+            if let Resource::SBITimer { freq_hz } = device.resource {
+                let timebase = timebase_frequency(fdt)?;
+
+                if freq_hz != timebase {
+                    return Err(format_err!(
+                        "Device {} runs at {} Hz, but the timebase frequency is {} Hz",
+                        device.name,
+                        freq_hz,
+                        timebase
+                    ));
+                }
+            }
+
+            return Ok(());
+        }
+    };
+
+    // Several nodes can describe the same address, e.g. a generic and a specific driver binding.
+    let candidates: Vec<(&NodeRef, Interval)> = nodes
+        .iter()
+        .filter(|n| n.node.is_enabled())
+        .filter_map(|n| {
+            n.reg()
+                .ok()
+                .and_then(|reg| reg.into_iter().find(|r| r.from == region.start))
+                .map(|r| (n, r))
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(format_err!(
+            "Device {} at {:#x} does not exist in the device tree",
+            device.name,
+            region.start
+        ));
+    }
+
+    let (node, reg) = candidates
+        .iter()
+        .find(|(n, _)| is_compatible(n, rtype))
+        .ok_or_else(|| {
+            format_err!(
+                "Device {} is a {:?}, but no node at {:#x} is compatible with it: {}",
+                device.name,
+                rtype,
+                region.start,
+                candidates
+                    .iter()
+                    .map(|(n, _)| format!(
+                        "{} ({})",
+                        n.node.name,
+                        n.node.strings("compatible").join(", ")
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        })?;
+
+    if reg.size() != region.size {
+        return Err(format_err!(
+            "Device {} has size {:#x}, but node {} has size {:#x}",
+            device.name,
+            region.size,
+            node.node.name,
+            reg.size()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that the memory and devices of a machine description match the device tree.
+pub fn check_machine(
+    available_memory: &[MemoryRegion],
+    devices: &[NamedResource],
+    fdt: &Fdt,
+) -> Result<(), Error> {
+    let nodes = fdt.nodes()?;
+    let memory = memory_regions(&nodes)?;
+    let reserved = reserved_regions(fdt, &nodes)?;
+
+    for region in available_memory {
+        check_memory(region, &memory, &reserved)?;
+    }
+
+    for device in devices {
+        check_device(device, &nodes, fdt)?;
+    }
+
+    Ok(())
+}
+
+/// The interrupt numbers of supervisor-mode (9) and machine-mode (11) external interrupts.
+const IRQ_S_EXT: u32 = 9;
+const IRQ_M_EXT: u32 = 11;
+
+/// Returns the phandle of the interrupt controller of a hart.
+fn intc_phandle(hart: u32) -> u32 {
+    hart + 1
+}
+
+fn cpu_node(hart: u32, class: ElfClass) -> Node {
+    let (isa, mmu) = match class {
+        ElfClass::Class32 => ("rv32ima", "riscv,sv32"),
+        ElfClass::Class64 => ("rv64ima", "riscv,sv39"),
+    };
+
+    Node::new(&format!("cpu@{:x}", hart))
+        .with_property("device_type", fdt::strings(&["cpu"]))
+        .with_property("reg", fdt::cells(&[hart]))
+        .with_property("status", fdt::strings(&["okay"]))
+        .with_property("compatible", fdt::strings(&["riscv"]))
+        .with_property("riscv,isa", fdt::strings(&[isa]))
+        .with_property("mmu-type", fdt::strings(&[mmu]))
+        .with_child(
+            Node::new("interrupt-controller")
+                .with_property("#interrupt-cells", fdt::cells(&[1]))
+                .with_property("interrupt-controller", vec![])
+                .with_property("compatible", fdt::strings(&["riscv,cpu-intc"]))
+                .with_property("phandle", fdt::cells(&[intc_phandle(hart)])),
+        )
+}
+
+/// Convert a resource of the kernel into a device node.
+fn device_node(resource: &runtypes::Resource, harts: u32) -> Result<Option<Node>, Error> {
+    let region = match &resource.opt_region {
+        Some(runtypes::VirtualMemoryRegion {
+            phys: runtypes::MemoryRegion::Phys { start, size },
+            ..
+        }) => Interval::new_with_size(*start, *size),
+        Some(_) => return Err(format_err!("Device regions must be physical memory")),
+        None => return Ok(None),
+    };
+
+    let node = |name: &str, rtype: ResourceType| {
+        Node::new(&format!("{}@{:x}", name, region.from))
+            .with_property("compatible", fdt::strings(compatible(rtype)))
+            .with_property("reg", fdt::reg64(&[region]))
+    };
+
+    Ok(Some(match &resource.meta {
+        runtypes::ResourceMetaInfo::SifivePlic { ndev } => node("plic", ResourceType::SiFivePLIC)
+            .with_property("riscv,ndev", fdt::cells(&[(*ndev).into()]))
+            .with_property("#address-cells", fdt::cells(&[0]))
+            .with_property("#interrupt-cells", fdt::cells(&[1]))
+            .with_property("interrupt-controller", vec![])
+            .with_property("phandle", fdt::cells(&[harts + 1]))
+            .with_property(
+                "interrupts-extended",
+                fdt::cells(
+                    &(0..harts)
+                        .flat_map(|h| vec![intc_phandle(h), IRQ_M_EXT, intc_phandle(h), IRQ_S_EXT])
+                        .collect::<Vec<u32>>(),
+                ),
+            ),
+        runtypes::ResourceMetaInfo::Framebuffer { format } => {
+            node("framebuffer", ResourceType::Framebuffer)
+                .with_property("width", fdt::cells(&[format.width]))
+                .with_property("height", fdt::cells(&[format.height]))
+                .with_property("stride", fdt::cells(&[format.stride]))
+                .with_property("format", fdt::strings(&[pixel_format_name(format.pixel)]))
+        }
+        runtypes::ResourceMetaInfo::SpinalGPIO { ngpio } => node("gpio", ResourceType::SpinalGPIO)
+            .with_property("ngpio", fdt::cells(&[(*ngpio).into()]))
+            .with_property("gpio-controller", vec![])
+            .with_property("#gpio-cells", fdt::cells(&[2])),
+        runtypes::ResourceMetaInfo::SBITimer { .. } => return Ok(None),
+    }))
+}
+
+/// Build a device tree for a configured system.
+///
+/// `resources` are the devices the kernel uses. The SBI timer determines the timebase frequency.
+pub fn synthesize(
+    name: &str,
+    class: ElfClass,
+    available_memory: &[MemoryRegion],
+    harts: u64,
+    resources: &[&runtypes::Resource],
+) -> Result<Fdt, Error> {
+    let harts: u32 = harts.try_into()?;
+    let freq_hz = resources
+        .iter()
+        .find_map(|r| match r.meta {
+            runtypes::ResourceMetaInfo::SBITimer { freq_hz } => Some(freq_hz),
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("The device tree needs the frequency of the SBI timer"))?;
+
+    let mut cpus = Node::new("cpus")
+        .with_property("#address-cells", fdt::cells(&[1]))
+        .with_property("#size-cells", fdt::cells(&[0]))
+        .with_property("timebase-frequency", fdt::cells(&[freq_hz.try_into()?]));
+
+    for hart in 0..harts {
+        cpus = cpus.with_child(cpu_node(hart, class));
+    }
+
+    let mut soc = Node::new("soc")
+        .with_property("#address-cells", fdt::cells(&[2]))
+        .with_property("#size-cells", fdt::cells(&[2]))
+        .with_property("compatible", fdt::strings(&["simple-bus"]))
+        .with_property("ranges", vec![]);
+
+    for resource in resources {
+        if let Some(node) = device_node(resource, harts)? {
+            soc = soc.with_child(node);
+        }
+    }
+
+    let mut root = Node::new("")
+        .with_property("#address-cells", fdt::cells(&[2]))
+        .with_property("#size-cells", fdt::cells(&[2]))
+        .with_property("compatible", fdt::strings(&["epoxy"]))
+        .with_property("model", fdt::strings(&[name]))
+        .with_child(cpus);
+
+    for region in available_memory {
+        root = root.with_child(
+            Node::new(&format!("memory@{:x}", region.start))
+                .with_property("device_type", fdt::strings(&["memory"]))
+                .with_property(
+                    "reg",
+                    fdt::reg64(&[Interval::new_with_size(region.start, region.size)]),
+                ),
+        );
+    }
+
+    Ok(Fdt {
+        boot_cpuid: 0,
+        reserved: vec![],
+        root: root.with_child(soc),
+    })
+}
+
+fn region_to_dhall(region: &MemoryRegion) -> String {
+    format!(
+        "{{ start = {:#x}, size = {:#x} }}",
+        region.start, region.size
+    )
+}
+
+/// Format a list in the style of dhall format with the given indentation.
+fn list_to_dhall(items: &[String], indent: usize, empty_type: &str) -> String {
+    let pad = " ".repeat(indent);
+
+    if items.is_empty() {
+        return format!("{}[] : List {}", pad, empty_type);
+    }
+
+    format!(
+        "{}{}]",
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| format!("{}{} {}\n", pad, if i == 0 { "[" } else { "," }, item))
+            .join(""),
+        pad
+    )
+}
+
+fn resource_to_dhall(resource: &Resource) -> String {
+    match resource {
+        Resource::Framebuffer { format, region } => format!(
+            "Epoxy.Resource.Framebuffer
+                {{ format =
+                  {{ height = {}
+                  , width = {}
+                  , stride = {}
+                  , pixel = Epoxy.PixelFormat.{:?}
+                  }}
+                , region = {}
+                }}",
+            format.height,
+            format.width,
+            format.stride,
+            format.pixel,
+            region_to_dhall(region)
+        ),
+        Resource::SiFivePLIC { ndev, region } => format!(
+            "Epoxy.Resource.SiFivePLIC
+                {{ ndev = {:#x}
+                , region = {}
+                }}",
+            ndev,
+            region_to_dhall(region)
+        ),
+        Resource::SBITimer { freq_hz } => {
+            format!("Epoxy.Resource.SBITimer {{ freq_hz = {} }}", freq_hz)
+        }
+        Resource::SpinalGPIO { ngpio, region } => format!(
+            "Epoxy.Resource.SpinalGPIO
+                {{ ngpio = {:#x}
+                , region = {}
+                }}",
+            ngpio,
+            region_to_dhall(region)
+        ),
+    }
+}
+
+/// Render a machine description as a Dhall file for the `machines/` directory.
+pub fn to_dhall(machine: &Machine) -> String {
+    let memory: Vec<String> = machine
+        .available_memory
+        .iter()
+        .map(region_to_dhall)
+        .collect();
+    let devices: Vec<String> = machine
+        .devices
+        .iter()
+        .map(|d| {
+            let resource = resource_to_dhall(&d.resource);
+
+            // Resources that fit on one line are not broken up by dhall format.
+            if resource.contains('\n') {
+                format!(
+                    "{{ name = \"{}\"\n          , resource =\n              {}\n          }}",
+                    d.name, resource
+                )
+            } else {
+                format!(
+                    "{{ name = \"{}\"\n          , resource = {}\n          }}",
+                    d.name, resource
+                )
+            }
+        })
+        .collect();
+
+    format!(
+        "let Epoxy = ../types/Epoxy.dhall
+
+in    {{ name = \"{}\"
+      , harts = {}
+      , available_memory =
+{}
+      , devices =
+{}
+      }}
+    : Epoxy.Machine
+",
+        machine.name,
+        machine.harts,
+        list_to_dhall(&memory, 8, "Epoxy.MemoryRegion"),
+        list_to_dhall(&devices, 8, "Epoxy.NamedResource")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(dtb: &[u8]) -> Machine {
+        import_machine("test", &Fdt::parse(dtb).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_subtract() {
+        let r = Interval { from: 0, to: 10 };
+
+        assert_eq!(
+            subtract(&[r], Interval { from: 2, to: 4 }),
+            vec![Interval { from: 0, to: 2 }, Interval { from: 4, to: 10 }]
+        );
+        assert_eq!(subtract(&[r], Interval { from: 0, to: 20 }), vec![]);
+        assert_eq!(subtract(&[r], Interval { from: 10, to: 20 }), vec![r]);
+    }
+
+    #[test]
+    fn test_import_qemu() {
+        let machine = import(include_bytes!("../testdata/qemu-virt.dtb"));
+
+        assert_eq!(machine.harts, 1);
+
+        // OpenSBI's reserved memory at the start of RAM is not available.
+        assert_eq!(
+            machine
+                .available_memory
+                .iter()
+                .map(|r| (r.start, r.size))
+                .collect::<Vec<_>>(),
+            vec![(0x80040000, 0x7fc0000)]
+        );
+        assert!(matches!(
+            machine
+                .devices
+                .iter()
+                .find(|d| d.name == "sbitimer")
+                .map(|d| &d.resource),
+            Some(Resource::SBITimer { freq_hz: 10000000 })
+        ));
+    }
+
+    #[test]
+    fn test_import_saxonsoc() {
+        let machine = import(include_bytes!("../testdata/ulx3s-saxonsoc.dtb"));
+
+        // The framebuffer and the memory reservation are carved out of memory.
+        assert_eq!(
+            machine
+                .available_memory
+                .iter()
+                .map(|r| (r.start, r.size))
+                .collect::<Vec<_>>(),
+            vec![(0x80800000, 0x600000), (0x80e96000, 0x116a000)]
+        );
+        assert_eq!(
+            machine
+                .devices
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["gpio", "plic", "framebuffer", "sbitimer"]
+        );
+    }
+
+    fn saxonsoc() -> Fdt {
+        Fdt::parse(include_bytes!("../testdata/ulx3s-saxonsoc.dtb")).unwrap()
+    }
+
+    fn check(machine: &Machine, fdt: &Fdt) -> Result<(), Error> {
+        check_machine(&machine.available_memory, &machine.devices, fdt)
+    }
+
+    fn device<'a>(machine: &'a mut Machine, name: &str) -> &'a mut Resource {
+        &mut machine
+            .devices
+            .iter_mut()
+            .find(|d| d.name == name)
+            .unwrap()
+            .resource
+    }
+
+    /// Insert an incompatible node at the same address in front of the node with the given name.
+    fn shadow(node: &mut Node, name: &str) {
+        if let Some(i) = node.children.iter().position(|c| c.name == name) {
+            let mut other = node.children[i].clone();
+
+            other.properties.retain(|(n, _)| n != "compatible");
+            other.properties.push((
+                "compatible".to_string(),
+                fdt::strings(&["vendor,something-else"]),
+            ));
+            node.children.insert(i, other);
+        } else {
+            node.children.iter_mut().for_each(|c| shadow(c, name));
+        }
+    }
+
+    #[test]
+    fn test_check_machine() {
+        let machine: Machine = serde_dhall::from_file("../config/machines/ulx3s-saxonsoc.dhall")
+            .parse()
+            .unwrap();
+        let fdt = saxonsoc();
+
+        check(&machine, &fdt).unwrap();
+
+        // Memory that overlaps the memory reservation.
+        let mut bad_memory = machine.clone();
+        bad_memory.available_memory[0].start = 0x80400000;
+        assert!(check(&bad_memory, &fdt).is_err());
+
+        // A device with the wrong size.
+        let mut bad_size = machine.clone();
+        if let Resource::SpinalGPIO { region, .. } = device(&mut bad_size, "gpio") {
+            region.size = 0x2000;
+        }
+        assert!(check(&bad_size, &fdt).is_err());
+
+        // A device at an address where the device tree has another device.
+        let mut bad_type = machine.clone();
+        *device(&mut bad_type, "gpio") = Resource::SiFivePLIC {
+            ndev: 1,
+            region: MemoryRegion {
+                start: 0x10000000,
+                size: 0x1000,
+            },
+        };
+        assert!(check(&bad_type, &fdt).is_err());
+
+        let mut bad_freq = machine.clone();
+        *device(&mut bad_freq, "sbitimer") = Resource::SBITimer { freq_hz: 1000000 };
+        assert!(check(&bad_freq, &fdt).is_err());
+
+        // Another node at the same address doesn't matter as long as one node is compatible.
+        let mut shadowed = fdt;
+        shadow(&mut shadowed.root, "gpio@0");
+        check(&machine, &shadowed).unwrap();
+        assert!(check(&bad_type, &shadowed).is_err());
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let machine: Machine = serde_dhall::from_file("../config/machines/ulx3s-saxonsoc.dhall")
+            .parse()
+            .unwrap();
+        let resources = [
+            runtypes::Resource {
+                meta: runtypes::ResourceMetaInfo::SifivePlic { ndev: 0x20 },
+                opt_region: Some(runtypes::VirtualMemoryRegion {
+                    virt_start: 0x88000000,
+                    phys: runtypes::MemoryRegion::Phys {
+                        start: 0x10c00000,
+                        size: 0x400000,
+                    },
+                }),
+            },
+            runtypes::Resource {
+                meta: runtypes::ResourceMetaInfo::SBITimer { freq_hz: 52000000 },
+                opt_region: None,
+            },
+        ];
+
+        let fdt = synthesize(
+            "test",
+            ElfClass::Class32,
+            &machine.available_memory,
+            2,
+            &resources.iter().collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let fdt = Fdt::parse(&fdt.to_bytes()).unwrap();
+
+        // Reading the device tree back gives us the machine description again.
+        let imported = import_machine("test", &fdt).unwrap();
+
+        assert_eq!(imported.harts, 2);
+        assert_eq!(
+            format!("{:?}", imported.available_memory),
+            format!("{:?}", machine.available_memory)
+        );
+        assert_eq!(
+            format!("{:?}", imported.devices),
+            format!(
+                "{:?}",
+                machine
+                    .devices
+                    .iter()
+                    .filter(|d| d.name == "plic" || d.name == "sbitimer")
+                    .collect::<Vec<_>>()
+            )
+        );
+    }
+
+    #[test]
+    fn test_to_dhall() {
+        let machine = import(include_bytes!("../testdata/ulx3s-saxonsoc.dtb"));
+
+        // The generated file imports the types relative to the machines directory.
+        let parsed: Machine =
+            serde_dhall::from_str(&to_dhall(&machine).replace("../types/", "../config/types/"))
+                .parse()
+                .unwrap();
+
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", machine));
+    }
+}
@@ -1,12 +1,20 @@
 //! Abstract the underlying ELF libary and expose the simple bit of functionality we need.
 
-use anyhow::Error;
+use anyhow::{Context, Error};
+use goblin::elf::header;
+use goblin::elf::program_header::ProgramHeader;
+use goblin::elf::reloc::{self, Reloc};
+use goblin::elf::section_header::{SHN_ABS, SHN_UNDEF};
+use goblin::elf::sym;
 use goblin::elf64::header::EI_CLASS;
+use goblin::elf64::header::EI_DATA;
 use goblin::elf64::header::ELFCLASS32;
 use goblin::elf64::header::ELFCLASS64;
+use goblin::elf64::header::ELFDATA2LSB;
+use goblin::elf64::header::ELFDATA2MSB;
 use goblin::Object;
 use std::collections::BTreeMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::fs;
 use std::path::Path;
@@ -21,6 +29,15 @@ pub struct Permissions {
 }
 
 impl Permissions {
+    pub fn read_only() -> Permissions {
+        Permissions {
+            read: true,
+            write: false,
+            execute: false,
+            user: false,
+        }
+    }
+
     pub fn read_write() -> Permissions {
         Permissions {
             read: true,
@@ -29,6 +46,26 @@ impl Permissions {
             user: false,
         }
     }
+
+    /// Readable, writable and executable by the kernel.
+    pub fn all() -> Permissions {
+        Permissions {
+            read: true,
+            write: true,
+            execute: true,
+            user: false,
+        }
+    }
+
+    /// Returns the permissions that allow everything either of the two allows.
+    pub fn union(self, other: Permissions) -> Permissions {
+        Permissions {
+            read: self.read || other.read,
+            write: self.write || other.write,
+            execute: self.execute || other.execute,
+            user: self.user || other.user,
+        }
+    }
 }
 
 impl fmt::Debug for Permissions {
@@ -66,87 +103,885 @@ pub struct Segment {
     pub data: Vec<u8>,
 }
 
-type SymbolMap = BTreeMap<String, u64>;
+/// The template for thread-local storage blocks from the PT_TLS segment.
+#[derive(Debug, Clone)]
+pub struct TlsTemplate {
+    /// The virtual address of the PT_TLS segment. Its initialized data usually lies in a PT_LOAD
+    /// segment as well.
+    pub vaddr: u64,
+
+    /// The initial content of a TLS block: the initialized data (.tdata) followed by zeroes
+    /// (.tbss).
+    pub data: Vec<u8>,
+
+    /// The alignment the TLS block needs.
+    pub align: u64,
+}
+
+/// A dynamic relocation of a position-independent ELF. Everything except the load base is already
+/// resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// The virtual address of the patched word for a load base of zero.
+    pub offset: u64,
+
+    /// The size of the patched word in bytes.
+    pub size: u8,
+
+    /// The value of the patched word minus the load base.
+    pub value: u64,
+}
+
+/// The type of an ELF symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    NoType,
+    Object,
+    Func,
+    Section,
+    File,
+    Tls,
+
+    /// Any symbol type we don't care about, e.g. processor-specific ones.
+    Other(u8),
+}
+
+impl From<u8> for SymbolType {
+    fn from(st_type: u8) -> Self {
+        match st_type {
+            sym::STT_NOTYPE => SymbolType::NoType,
+            sym::STT_OBJECT => SymbolType::Object,
+            sym::STT_FUNC => SymbolType::Func,
+            sym::STT_SECTION => SymbolType::Section,
+            sym::STT_FILE => SymbolType::File,
+            sym::STT_TLS => SymbolType::Tls,
+            t => SymbolType::Other(t),
+        }
+    }
+}
+
+impl From<SymbolType> for u8 {
+    fn from(t: SymbolType) -> Self {
+        match t {
+            SymbolType::NoType => sym::STT_NOTYPE,
+            SymbolType::Object => sym::STT_OBJECT,
+            SymbolType::Func => sym::STT_FUNC,
+            SymbolType::Section => sym::STT_SECTION,
+            SymbolType::File => sym::STT_FILE,
+            SymbolType::Tls => sym::STT_TLS,
+            SymbolType::Other(t) => t,
+        }
+    }
+}
+
+/// A symbol from the ELF symbol table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    /// The virtual address of the symbol.
+    pub vaddr: u64,
+
+    /// The size of the symbol in bytes. This is zero for symbols without size information.
+    pub size: u64,
 
+    pub r#type: SymbolType,
+}
+
+type SymbolMap = BTreeMap<String, Symbol>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ElfClass {
     Class32,
     Class64,
 }
 
+impl ElfClass {
+    /// The first address that is not representable in this class.
+    fn address_limit(self) -> u128 {
+        match self {
+            ElfClass::Class32 => 1 << 32,
+            ElfClass::Class64 => u128::from(u64::MAX) + 1,
+        }
+    }
+
+    /// The size of a machine word in bytes.
+    fn word_size(self) -> u8 {
+        match self {
+            ElfClass::Class32 => 4,
+            ElfClass::Class64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Endianness::Little => "little-endian",
+            Endianness::Big => "big-endian",
+        })
+    }
+}
+
+pub const EM_RISCV: u16 = header::EM_RISCV;
+
+/// The binary uses compressed instructions.
+const EF_RISCV_RVC: u32 = 0x1;
+
+/// The floating point calling convention.
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+
+/// The binary targets the embedded base ISA.
+const EF_RISCV_RVE: u32 = 0x8;
+
+fn riscv_float_abi(flags: u32) -> &'static str {
+    match flags & EF_RISCV_FLOAT_ABI {
+        0x0 => "soft-float",
+        0x2 => "single-float",
+        0x4 => "double-float",
+        _ => "quad-float",
+    }
+}
+
+/// Check the RISC-V-specific ABI flags of `other` against the flags of the ELF we expect.
+fn check_riscv_flags(expected: u32, other: u32) -> Result<(), Error> {
+    let rve = |flags| if flags & EF_RISCV_RVE != 0 { "E" } else { "I" };
+
+    if expected & EF_RISCV_FLOAT_ABI != other & EF_RISCV_FLOAT_ABI {
+        Err(format_err!(
+            "ELF uses the {} ABI, but expected the {} ABI",
+            riscv_float_abi(other),
+            riscv_float_abi(expected)
+        ))
+    } else if expected & EF_RISCV_RVE != other & EF_RISCV_RVE {
+        Err(format_err!(
+            "ELF targets the {} base ISA, but expected {}",
+            rve(other),
+            rve(expected)
+        ))
+    } else if other & EF_RISCV_RVC != 0 && expected & EF_RISCV_RVC == 0 {
+        Err(format_err!(
+            "ELF uses compressed instructions, but expected none"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns a human-readable name for an ELF machine type.
+pub fn machine_name(machine: u16) -> String {
+    match header::machine_to_str(machine) {
+        "EM_UNKNOWN" => format!("machine {:#x}", machine),
+        name => name.to_string(),
+    }
+}
+
 pub struct Elf {
     pub class: ElfClass,
+    pub endianness: Endianness,
+
+    /// The machine type (`e_machine`).
+    pub machine: u16,
+
+    /// Processor-specific flags (`e_flags`), e.g. the float ABI on RISC-V.
+    pub flags: u32,
 
     /// Entry point of the ELF.
     pub entry: u64,
 
+    /// The ELF is position-independent (`ET_DYN`) and can be loaded at any page-aligned address
+    /// with `relocate`.
+    pub pie: bool,
+
     pub segments: Vec<Segment>,
+
+    /// The dynamic relocations that need to be applied when a position-independent ELF is loaded.
+    pub relocations: Vec<Relocation>,
+
+    /// The thread-local storage template, if the ELF uses thread-local variables.
+    pub tls: Option<TlsTemplate>,
+
     pub symbols: SymbolMap,
 }
 
-fn elf_subslice(data: &[u8], offset: u64, filesz: u64, memsz: u64) -> Result<Vec<u8>, Error> {
+/// The largest amount of memory all segments of an ELF may occupy together. Anything larger is
+/// almost certainly a malformed binary and would only exhaust memory.
+const MAX_LOAD_SIZE: u64 = 1 << 30;
+
+/// Returns the content of a PT_LOAD or PT_TLS segment with its zero-filled tail. All values come straight
+/// from the file and are validated before they are used.
+fn segment_data(data: &[u8], class: ElfClass, ph: &ProgramHeader) -> Result<Vec<u8>, Error> {
+    let (offset, filesz, memsz) = (ph.p_offset, ph.p_filesz, ph.p_memsz);
+    let address_limit = class.address_limit();
+
     if memsz < filesz {
-        Err(format_err!(
-            "Invalid ELF segment: filesz {} vs memsz {}",
+        return Err(format_err!(
+            "Invalid ELF segment: filesz {:#x} exceeds memsz {:#x}",
             filesz,
             memsz
+        ));
+    }
+
+    for (name, addr) in &[("vaddr", ph.p_vaddr), ("paddr", ph.p_paddr)] {
+        if u128::from(*addr) + u128::from(memsz) > address_limit {
+            return Err(format_err!(
+                "Invalid ELF segment: {} {:#x} with memsz {:#x} overflows",
+                name,
+                addr,
+                memsz
+            ));
+        }
+    }
+
+    if ph.p_align > 1 {
+        if !ph.p_align.is_power_of_two() {
+            return Err(format_err!(
+                "Invalid ELF segment: alignment {:#x} is not a power of two",
+                ph.p_align
+            ));
+        }
+
+        if ph.p_vaddr % ph.p_align != offset % ph.p_align {
+            return Err(format_err!(
+                "Invalid ELF segment: vaddr {:#x} and offset {:#x} disagree modulo alignment {:#x}",
+                ph.p_vaddr,
+                offset,
+                ph.p_align
+            ));
+        }
+    }
+
+    let file_slice = offset
+        .checked_add(filesz)
+        .and_then(|end| data.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?))
+        .ok_or_else(|| {
+            format_err!(
+                "Invalid ELF segment: {:#x} bytes at offset {:#x} exceed the file size of {:#x}",
+                filesz,
+                offset,
+                data.len()
+            )
+        })?;
+
+    let mut segment_data = file_slice.to_vec();
+
+    segment_data.resize(memsz.try_into()?, 0);
+    Ok(segment_data)
+}
+
+/// Resolve a RISC-V dynamic relocation as far as possible without knowing the load base. Only
+/// relocations that are relative to the load base are supported, which is all a static PIE needs.
+fn riscv_relocation(
+    elf: &goblin::elf::Elf,
+    class: ElfClass,
+    r: &Reloc,
+) -> Result<Relocation, Error> {
+    let addend = r
+        .r_addend
+        .ok_or_else(|| format_err!("Relocations without addend are not supported"))?;
+    let symbol = || -> Result<u64, Error> {
+        let sym = elf
+            .dynsyms
+            .get(r.r_sym)
+            .ok_or_else(|| format_err!("Relocation refers to invalid symbol {}", r.r_sym))?;
+        let name = elf
+            .dynstrtab
+            .get(sym.st_name)
+            .and_then(|n| n.ok())
+            .unwrap_or("");
+
+        if sym.st_shndx == SHN_UNDEF as usize {
+            Err(format_err!(
+                "Relocation refers to undefined symbol '{}'",
+                name
+            ))
+        } else if sym.st_shndx == SHN_ABS as usize {
+            Err(format_err!(
+                "Relocation refers to absolute symbol '{}'",
+                name
+            ))
+        } else {
+            Ok(sym.st_value)
+        }
+    };
+
+    let (size, value) = match r.r_type {
+        reloc::R_RISCV_RELATIVE => (class.word_size(), addend as u64),
+        reloc::R_RISCV_32 => (4, symbol()?.wrapping_add(addend as u64)),
+        reloc::R_RISCV_64 => (8, symbol()?.wrapping_add(addend as u64)),
+        reloc::R_RISCV_JUMP_SLOT => (class.word_size(), symbol()?),
+        t => return Err(format_err!("Unsupported relocation type {}", t)),
+    };
+
+    Ok(Relocation {
+        offset: r.r_offset,
+        size,
+        value,
+    })
+}
+
+/// Returns the dynamic relocations of an ELF.
+/// Overwrite the bytes at virtual address `offset` in `data`, which starts at `vaddr`. Returns
+/// false, if they are not completely inside `data`.
+fn patch(data: &mut [u8], vaddr: u64, offset: u64, bytes: &[u8]) -> bool {
+    let start = match offset
+        .checked_sub(vaddr)
+        .and_then(|start| usize::try_from(start).ok())
+    {
+        Some(start) => start,
+        None => return false,
+    };
+
+    match data.get_mut(start..start.saturating_add(bytes.len())) {
+        Some(target) if target.len() == bytes.len() => {
+            target.copy_from_slice(bytes);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn dynamic_relocations(elf: &goblin::elf::Elf, class: ElfClass) -> Result<Vec<Relocation>, Error> {
+    let relocs = elf
+        .dynrelas
+        .iter()
+        .chain(elf.dynrels.iter())
+        .chain(elf.pltrelocs.iter())
+        .collect::<Vec<Reloc>>();
+
+    if relocs.is_empty() {
+        Ok(vec![])
+    } else if elf.header.e_machine != EM_RISCV {
+        Err(format_err!(
+            "Relocations are not supported for {}",
+            machine_name(elf.header.e_machine)
         ))
     } else {
-        let file_slice: &[u8] = &data[offset.try_into()?..(offset + filesz).try_into()?];
-        let zeros: &[u8] = &vec![0; (memsz - filesz).try_into()?];
-
-        // TODO As the integers are potentially wrong, this needs real input validation and checks
-        // for integer overflow.
-        Ok([file_slice, zeros].concat())
+        relocs
+            .iter()
+            .map(|r| {
+                riscv_relocation(elf, class, r)
+                    .with_context(|| format!("Failed to resolve relocation at {:#x}", r.r_offset))
+            })
+            .collect()
     }
 }
 
 fn elf_symbols(elf: &goblin::elf::Elf) -> Result<SymbolMap, Error> {
     elf.syms
         .iter()
-        .map(|s| -> Result<(String, u64), Error> {
+        .map(|s| -> Result<(String, Symbol), Error> {
             let name = elf
                 .strtab
                 .get(s.st_name)
                 .ok_or_else(|| format_err!("Failed to find symbol name in ELF"))??;
 
-            Ok((name.to_string(), s.st_value))
+            Ok((
+                name.to_string(),
+                Symbol {
+                    vaddr: s.st_value,
+                    size: s.st_size,
+                    r#type: s.st_type().into(),
+                },
+            ))
         })
         .collect::<Result<SymbolMap, Error>>()
 }
 
 impl Elf {
+    /// Check that `other` was built for the same kind of machine and ABI as this ELF, so both can
+    /// run together.
+    ///
+    /// On RISC-V, the float ABI and base ISA must match. `other` may only use compressed
+    /// instructions, if this ELF does.
+    pub fn check_compatible(&self, other: &Elf) -> Result<(), Error> {
+        let bits = |class| match class {
+            ElfClass::Class32 => 32,
+            ElfClass::Class64 => 64,
+        };
+
+        if self.class != other.class {
+            Err(format_err!(
+                "ELF is {}-bit, but expected {}-bit",
+                bits(other.class),
+                bits(self.class)
+            ))
+        } else if self.endianness != other.endianness {
+            Err(format_err!(
+                "ELF is {}, but expected {}",
+                other.endianness,
+                self.endianness
+            ))
+        } else if self.machine != other.machine {
+            Err(format_err!(
+                "ELF is built for {}, but expected {}",
+                machine_name(other.machine),
+                machine_name(self.machine)
+            ))
+        } else if self.machine == EM_RISCV {
+            check_riscv_flags(self.flags, other.flags)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Move a position-independent ELF to `base` and apply its relocations.
+    pub fn relocate(&mut self, base: u64) -> Result<(), Error> {
+        if !self.pie {
+            return Err(format_err!(
+                "ELF is not position-independent and cannot be loaded at {:#x}",
+                base
+            ));
+        }
+
+        for segment in &self.segments {
+            let len: u64 = segment.data.len().try_into()?;
+
+            if u128::from(base) + u128::from(segment.vaddr) + u128::from(len)
+                > self.class.address_limit()
+            {
+                return Err(format_err!(
+                    "Segment at {:#x} does not fit at load base {:#x}",
+                    segment.vaddr,
+                    base
+                ));
+            }
+        }
+
+        for r in &self.relocations {
+            let value = base.wrapping_add(r.value);
+            let bytes = match (r.size, self.endianness) {
+                (4, endianness) => {
+                    let value = u32::try_from(value).map_err(|_| {
+                        format_err!(
+                            "Relocated value {:#x} at {:#x} does not fit into 32 bits",
+                            value,
+                            r.offset
+                        )
+                    })?;
+
+                    match endianness {
+                        Endianness::Little => value.to_le_bytes().to_vec(),
+                        Endianness::Big => value.to_be_bytes().to_vec(),
+                    }
+                }
+                (_, Endianness::Little) => value.to_le_bytes().to_vec(),
+                (_, Endianness::Big) => value.to_be_bytes().to_vec(),
+            };
+
+            // The initialized TLS data is usually part of a PT_LOAD segment, but the template is a
+            // separate copy, so relocations inside it have to be applied to both.
+            let in_segment = self
+                .segments
+                .iter_mut()
+                .any(|s| patch(&mut s.data, s.vaddr, r.offset, &bytes));
+            let in_tls = self
+                .tls
+                .as_mut()
+                .map_or(false, |t| patch(&mut t.data, t.vaddr, r.offset, &bytes));
+
+            if !in_segment && !in_tls {
+                return Err(format_err!(
+                    "Relocation at {:#x} is outside of all segments",
+                    r.offset
+                ));
+            }
+        }
+
+        for segment in &mut self.segments {
+            segment.vaddr += base;
+            segment.paddr = segment.paddr.wrapping_add(base);
+        }
+
+        for sym in self.symbols.values_mut() {
+            sym.vaddr = sym.vaddr.wrapping_add(base);
+        }
+
+        if let Some(tls) = &mut self.tls {
+            tls.vaddr = tls.vaddr.wrapping_add(base);
+        }
+
+        self.entry = self.entry.wrapping_add(base);
+        self.relocations.clear();
+
+        Ok(())
+    }
+
     pub fn new(path: &Path) -> Result<Elf, Error> {
         let data = fs::read(path)?;
 
-        match Object::parse(&data)? {
-            Object::Elf(elf) => Ok(Elf {
-                class: match elf.header.e_ident[EI_CLASS] {
-                    ELFCLASS32 => ElfClass::Class32,
-                    ELFCLASS64 => ElfClass::Class64,
-                    e => return Err(format_err!("Invalid ELF class {:x}", e)),
-                },
-                entry: elf.entry,
-                segments: elf
-                    .program_headers
-                    .iter()
-                    .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD)
-                    .map(|ph| -> Result<Segment, Error> {
-                        Ok(Segment {
-                            permissions: ph.into(),
-                            vaddr: ph.p_vaddr,
-                            paddr: ph.p_paddr,
-
-                            data: elf_subslice(&data, ph.p_offset, ph.p_filesz, ph.p_memsz)?,
-                        })
+        Elf::parse(&data).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Parse an ELF file from memory. This never panics, even if the data is malformed.
+    pub fn parse(data: &[u8]) -> Result<Elf, Error> {
+        let elf = match Object::parse(data)? {
+            Object::Elf(elf) => elf,
+            _ => return Err(format_err!("File format not recognized")),
+        };
+
+        let class = match elf.header.e_ident[EI_CLASS] {
+            ELFCLASS32 => ElfClass::Class32,
+            ELFCLASS64 => ElfClass::Class64,
+            e => return Err(format_err!("Invalid ELF class {:x}", e)),
+        };
+
+        let loads = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD)
+            .collect::<Vec<_>>();
+
+        let tls = match elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == goblin::elf::program_header::PT_TLS)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] => None,
+            [ph] => Some(*ph),
+            _ => return Err(format_err!("Invalid ELF: more than one PT_TLS segment")),
+        };
+
+        // Every thread gets its own copy of the TLS block, so it counts towards the memory the
+        // ELF needs as well.
+        let load_size = loads
+            .iter()
+            .chain(tls.iter())
+            .try_fold(0u64, |acc, ph| acc.checked_add(ph.p_memsz));
+
+        if load_size.map_or(true, |size| size > MAX_LOAD_SIZE) {
+            return Err(format_err!(
+                "Invalid ELF: segments need more than {:#x} bytes of memory",
+                MAX_LOAD_SIZE
+            ));
+        }
+
+        Ok(Elf {
+            class,
+            endianness: match elf.header.e_ident[EI_DATA] {
+                ELFDATA2LSB => Endianness::Little,
+                ELFDATA2MSB => Endianness::Big,
+                e => return Err(format_err!("Invalid ELF data encoding {:x}", e)),
+            },
+            machine: elf.header.e_machine,
+            flags: elf.header.e_flags,
+            entry: elf.entry,
+            pie: elf.header.e_type == header::ET_DYN,
+            segments: loads
+                .iter()
+                .map(|ph| -> Result<Segment, Error> {
+                    Ok(Segment {
+                        permissions: (*ph).into(),
+                        vaddr: ph.p_vaddr,
+                        paddr: ph.p_paddr,
+
+                        data: segment_data(data, class, ph).with_context(|| {
+                            format!("Failed to load segment at vaddr {:#x}", ph.p_vaddr)
+                        })?,
                     })
-                    .collect::<Result<Vec<Segment>, Error>>()?,
+                })
+                .collect::<Result<Vec<Segment>, Error>>()?,
+
+            relocations: if elf.header.e_type == header::ET_DYN {
+                dynamic_relocations(&elf, class)?
+            } else {
+                vec![]
+            },
 
-                symbols: elf_symbols(&elf)?,
+            tls: tls
+                .map(|ph| -> Result<TlsTemplate, Error> {
+                    Ok(TlsTemplate {
+                        vaddr: ph.p_vaddr,
+                        data: segment_data(data, class, ph)
+                            .context("Failed to load TLS segment")?,
+                        align: ph.p_align,
+                    })
+                })
+                .transpose()?,
+
+            symbols: elf_symbols(&elf)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf(class: ElfClass, endianness: Endianness, machine: u16) -> Elf {
+        Elf {
+            class,
+            endianness,
+            machine,
+            flags: 0,
+            entry: 0,
+            pie: false,
+            segments: vec![],
+            relocations: vec![],
+            tls: None,
+            symbols: SymbolMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        let kernel = elf(ElfClass::Class64, Endianness::Little, EM_RISCV);
+
+        assert!(kernel
+            .check_compatible(&elf(ElfClass::Class64, Endianness::Little, EM_RISCV))
+            .is_ok());
+        assert!(kernel
+            .check_compatible(&elf(ElfClass::Class32, Endianness::Little, EM_RISCV))
+            .is_err());
+        assert!(kernel
+            .check_compatible(&elf(ElfClass::Class64, Endianness::Big, EM_RISCV))
+            .is_err());
+
+        let err = kernel
+            .check_compatible(&elf(
+                ElfClass::Class64,
+                Endianness::Little,
+                header::EM_AARCH64,
+            ))
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "ELF is built for AARCH64, but expected RISCV"
+        );
+    }
+
+    #[test]
+    fn test_check_riscv_flags() {
+        let double_rvc = 0x4 | EF_RISCV_RVC;
+
+        assert!(check_riscv_flags(double_rvc, double_rvc).is_ok());
+        assert!(check_riscv_flags(double_rvc, 0x4).is_ok());
+        assert!(check_riscv_flags(0x4, double_rvc).is_err());
+        assert!(check_riscv_flags(0x0, EF_RISCV_RVE).is_err());
+
+        assert_eq!(
+            check_riscv_flags(double_rvc, EF_RISCV_RVC)
+                .unwrap_err()
+                .to_string(),
+            "ELF uses the soft-float ABI, but expected the double-float ABI"
+        );
+
+        let kernel = Elf {
+            flags: 0x2,
+            ..elf(ElfClass::Class32, Endianness::Little, EM_RISCV)
+        };
+
+        assert!(kernel
+            .check_compatible(&elf(ElfClass::Class32, Endianness::Little, EM_RISCV))
+            .is_err());
+    }
+
+    #[test]
+    fn test_relocate() {
+        let pie = |class, endianness, relocations| Elf {
+            pie: true,
+            entry: 0x100,
+            segments: vec![Segment {
+                permissions: Permissions::read_write(),
+                vaddr: 0x1000,
+                paddr: 0x1000,
+                data: vec![0; 0x10],
+            }],
+            relocations,
+            ..elf(class, endianness, EM_RISCV)
+        };
+        let reloc = |offset, size, value| Relocation {
+            offset,
+            size,
+            value,
+        };
+
+        let mut e = pie(
+            ElfClass::Class64,
+            Endianness::Little,
+            vec![reloc(0x1000, 8, 0x20), reloc(0x1008, 4, 0x30)],
+        );
+
+        e.relocate(0x10000).unwrap();
+        assert_eq!(e.entry, 0x10100);
+        assert_eq!(e.segments[0].vaddr, 0x11000);
+        assert_eq!(
+            e.segments[0].data,
+            vec![0x20, 0, 1, 0, 0, 0, 0, 0, 0x30, 0, 1, 0, 0, 0, 0, 0]
+        );
+        assert!(e.relocations.is_empty());
+
+        let mut e = pie(
+            ElfClass::Class32,
+            Endianness::Big,
+            vec![reloc(0x100c, 4, 0x30)],
+        );
+
+        e.relocate(0x10000).unwrap();
+        assert_eq!(&e.segments[0].data[0xc..], &[0, 1, 0, 0x30]);
+
+        // Relocations must stay within segments and relocated values need to fit.
+        assert!(pie(
+            ElfClass::Class64,
+            Endianness::Little,
+            vec![reloc(0x100c, 8, 0)]
+        )
+        .relocate(0x10000)
+        .is_err());
+        assert!(pie(
+            ElfClass::Class32,
+            Endianness::Little,
+            vec![reloc(0x1000, 4, 0xffff_0000)]
+        )
+        .relocate(0x10000)
+        .is_err());
+        assert!(pie(ElfClass::Class32, Endianness::Little, vec![])
+            .relocate(0xffff_f000)
+            .is_err());
+
+        assert!(elf(ElfClass::Class64, Endianness::Little, EM_RISCV)
+            .relocate(0x10000)
+            .is_err());
+
+        // Relocations in .tdata patch both the loaded segment and the TLS template. The template
+        // also covers .tbss, which is not part of any loaded data.
+        let mut e = Elf {
+            tls: Some(TlsTemplate {
+                vaddr: 0x1008,
+                data: vec![0; 0x10],
+                align: 8,
             }),
-            _ => Err(format_err!(
-                "File format of {} not recognized",
-                path.display()
-            )),
+            ..pie(
+                ElfClass::Class64,
+                Endianness::Little,
+                vec![reloc(0x1008, 8, 0x40), reloc(0x1010, 8, 0x50)],
+            )
+        };
+
+        e.relocate(0x10000).unwrap();
+        assert_eq!(&e.segments[0].data[8..], &[0x40, 0, 1, 0, 0, 0, 0, 0]);
+
+        let tls = e.tls.unwrap();
+        assert_eq!(tls.vaddr, 0x11008);
+        assert_eq!(
+            tls.data,
+            vec![0x40, 0, 1, 0, 0, 0, 0, 0, 0x50, 0, 1, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_segment_data() {
+        let data = [1, 2, 3, 4];
+        let ph = |p_offset, p_filesz, p_memsz| ProgramHeader {
+            p_offset,
+            p_filesz,
+            p_memsz,
+            p_vaddr: 0x1000,
+            p_paddr: 0x1000,
+            ..ProgramHeader::default()
+        };
+
+        assert_eq!(
+            segment_data(&data, ElfClass::Class64, &ph(1, 2, 4)).unwrap(),
+            vec![2, 3, 0, 0]
+        );
+
+        // Out of bounds or overflowing file ranges.
+        assert!(segment_data(&data, ElfClass::Class64, &ph(3, 2, 2)).is_err());
+        assert!(segment_data(&data, ElfClass::Class64, &ph(u64::MAX, 2, 2)).is_err());
+
+        // filesz larger than memsz.
+        assert!(segment_data(&data, ElfClass::Class64, &ph(0, 2, 1)).is_err());
+
+        // Segments that wrap around the address space.
+        let high = ProgramHeader {
+            p_vaddr: 0xffff_f000,
+            ..ph(0, 0, 0x2000)
+        };
+        assert!(segment_data(&data, ElfClass::Class64, &high).is_ok());
+        assert!(segment_data(&data, ElfClass::Class32, &high).is_err());
+
+        // Broken alignment.
+        let align = |p_align| ProgramHeader {
+            p_align,
+            ..ph(0, 4, 4)
+        };
+        assert!(segment_data(&data, ElfClass::Class64, &align(0x1000)).is_ok());
+        assert!(segment_data(&data, ElfClass::Class64, &align(0x1001)).is_err());
+
+        let misaligned = ProgramHeader {
+            p_offset: 1,
+            p_filesz: 3,
+            p_memsz: 3,
+            ..align(0x1000)
+        };
+        assert!(segment_data(&data, ElfClass::Class64, &misaligned).is_err());
+    }
+
+    /// A poor man's fuzzer that runs in every test run. See fuzz/ for the real thing.
+    #[test]
+    fn test_parse_malformed() {
+        use crate::elf_writer;
+        use crate::phys_mem::PhysMemory;
+
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+        let mut valid = vec![];
+
+        pmem.write(0x8000_0000, &[1, 2, 3, 4, 0, 0]);
+        elf_writer::write(
+            &mut valid,
+            elf_writer::Format {
+                class: ElfClass::Class64,
+                endianness: Endianness::Little,
+                machine: EM_RISCV,
+            },
+            0x8000_0000,
+            &pmem,
+            Some(&elf_writer::DebugInfo {
+                system_name: "test",
+                symbols: vec![elf_writer::Symbol {
+                    name: "sym".to_string(),
+                    addr: 0x8000_0000,
+                    size: 4,
+                    r#type: SymbolType::Object,
+                }],
+            }),
+        )
+        .unwrap();
+
+        let elf = Elf::parse(&valid).unwrap();
+        assert_eq!(elf.segments[0].data, vec![1, 2, 3, 4, 0, 0]);
+        assert!(elf.symbols.contains_key("sym"));
+
+        for len in 0..valid.len() {
+            let _ = Elf::parse(&valid[..len]);
+        }
+
+        // Corrupt every byte in a couple of ways. A simple linear congruential generator keeps
+        // this deterministic.
+        let mut rng: u32 = 1;
+
+        for pos in 0..valid.len() {
+            for _ in 0..4 {
+                rng = rng.wrapping_mul(1_103_515_245).wrapping_add(12345);
+
+                let mut corrupted = valid.clone();
+
+                corrupted[pos] = (rng >> 16) as u8;
+                let _ = Elf::parse(&corrupted);
+            }
+
+            let mut corrupted = valid.clone();
+
+            corrupted[pos] = 0xff;
+            let _ = Elf::parse(&corrupted);
         }
     }
 }
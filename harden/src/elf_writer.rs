@@ -7,8 +7,21 @@
 //!
 //! As an input we get a Memory structure that describes what regions
 //! of physical memory are populated. Each populated region becomes one
-//! segment in the ELF. Each segment needs a PHDR and all of the PHDRs
-//! are pointed to by the file header (EHDR) of the ELF file.
+//! or more segments in the ELF. Each segment needs a PHDR and all of
+//! the PHDRs are pointed to by the file header (EHDR) of the ELF file.
+//!
+//! Optionally, section headers, a symbol table and a note with the
+//! system name follow the segment data. They are not needed for
+//! booting, but allow debuggers and objdump to make sense of the image.
+//!
+//! Segments carry the permissions of the memory they contain and are
+//! page aligned: Their file offset is padded to be congruent to their
+//! physical address modulo the page size.
+//!
+//! Populated memory often contains lots of zeroes, e.g. for stacks and
+//! heaps. We don't store zeroes at the end of a segment in the file,
+//! but let the boot loader fill them in (memsz > filesz). Large runs of
+//! zeroes in the middle of a region split it into several segments.
 //!
 //! The structure of the ELF file we are creating thus looks like this:
 //!
@@ -33,57 +46,470 @@
 //!              +----------------------------+
 //!              | ... more segment data ...  |
 //!              +----------------------------+
+//!              | Debug information          |
+//!              |  (optional, see DebugInfo) |
+//!              +----------------------------+
 //!
 //! [1] https://en.wikipedia.org/wiki/Executable_and_Linkable_Format
 
 use anyhow::Error;
 use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
-use itertools::Itertools;
 use log::debug;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::io::Write;
 
+use crate::constants::PAGE_SIZE;
+use crate::elf::{Elf, ElfClass, Endianness, Permissions, SymbolType};
 use crate::phys_mem::{Chunk, PhysMemory};
 
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
-pub enum Format {
-    Elf32,
-    Elf64,
+/// Runs of zeroes inside a chunk that are at least this long split the chunk into separate
+/// segments. Shorter runs are cheaper to store than an additional PHDR.
+const MIN_ZERO_GAP: usize = PAGE_SIZE as usize;
+
+/// The type of ELF file to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub class: ElfClass,
+    pub endianness: Endianness,
+
+    /// The ELF machine type (`e_machine`).
+    pub machine: u16,
 }
 
-fn write_native<T: Write>(buf: &mut T, format: Format, value: u64) -> Result<(), Error> {
-    Ok(match format {
-        Format::Elf32 => buf.write_u32::<LittleEndian>(value.try_into()?)?,
-        Format::Elf64 => buf.write_u64::<LittleEndian>(value)?,
+impl From<&Elf> for Format {
+    /// Returns the format that matches an existing ELF, e.g. the kernel.
+    fn from(elf: &Elf) -> Self {
+        Format {
+            class: elf.class,
+            endianness: elf.endianness,
+            machine: elf.machine,
+        }
+    }
+}
+
+/// A loadable segment. Only `data` is stored in the file, the remaining `memsz - data.len()` bytes
+/// are zeroes.
+#[derive(Debug, PartialEq, Eq)]
+struct Segment<'a> {
+    paddr: u64,
+    data: &'a [u8],
+    memsz: u64,
+    perm: Permissions,
+}
+
+/// Returns the start and end of each run of zeroes of at least `min_len` bytes in `data`.
+fn zero_runs(data: &[u8], min_len: usize) -> Vec<(usize, usize)> {
+    let mut runs = vec![];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let start = pos + data[pos..].iter().take_while(|&&b| b != 0).count();
+        let end = start + data[start..].iter().take_while(|&&b| b == 0).count();
+
+        if end - start >= min_len {
+            runs.push((start, end));
+        }
+
+        pos = end;
+    }
+
+    runs
+}
+
+/// Split a chunk into segments around large runs of zeroes and move trailing zeroes out of the
+/// file data.
+fn chunk_segments(chunk: &Chunk) -> Vec<Segment<'_>> {
+    let data = chunk.data.as_slice();
+    let mut runs = zero_runs(data, MIN_ZERO_GAP);
+
+    // Trailing zeroes never need to be stored, however few there are.
+    let trailing = data.iter().rev().take_while(|&&b| b == 0).count();
+    if trailing > 0 && runs.last().map(|&(_, end)| end) != Some(data.len()) {
+        runs.push((data.len() - trailing, data.len()));
+    }
+
+    // Each segment starts at the beginning of the chunk or after a run of zeroes and covers the
+    // following run of zeroes with its memory size.
+    let starts = std::iter::once(0).chain(runs.iter().map(|&(_, end)| end));
+    let ends = runs
+        .iter()
+        .copied()
+        .chain(std::iter::once((data.len(), data.len())));
+
+    starts
+        .zip(ends)
+        .filter(|&(from, (_, mem_end))| mem_end > from)
+        .map(|(from, (file_end, mem_end))| Segment {
+            paddr: chunk.paddr + from as u64,
+            data: &data[from..file_end],
+            memsz: (mem_end - from) as u64,
+            perm: chunk.perm,
+        })
+        .collect()
+}
+
+fn segments(chunks: &[Chunk]) -> Vec<Segment<'_>> {
+    chunks.iter().flat_map(chunk_segments).collect()
+}
+
+/// A symbol for the symbol table of the boot image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+
+    /// The physical address of the symbol.
+    pub addr: u64,
+
+    pub size: u64,
+    pub r#type: SymbolType,
+}
+
+/// Information for debuggers that is added to the boot image as sections.
+#[derive(Debug, Clone)]
+pub struct DebugInfo<'a> {
+    /// The name of the system, which ends up in the `.note.epoxy` section.
+    pub system_name: &'a str,
+
+    pub symbols: Vec<Symbol>,
+}
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+const SHN_ABS: u16 = 0xfff1;
+const STB_GLOBAL: u8 = 1;
+
+/// The owner of the notes we write.
+const NOTE_NAME: &[u8] = b"Epoxy\0";
+
+/// The note type for the system name.
+const NT_EPOXY_SYSTEM_NAME: u32 = 1;
+
+#[derive(Debug, Default, Clone)]
+struct SectionHeader {
+    name: u32,
+    sh_type: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl SectionHeader {
+    fn contains(&self, addr: u64) -> bool {
+        self.flags & SHF_ALLOC != 0 && addr >= self.addr && addr - self.addr < self.size
+    }
+}
+
+/// The section headers and the data of non-allocated sections, which follows the segment data in
+/// the file.
+struct Sections {
+    headers: Vec<SectionHeader>,
+    data: Vec<u8>,
+
+    /// The index of the section that holds the section names.
+    shstrndx: usize,
+}
+
+/// A string table as used for symbol and section names.
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        // The first string is always the empty one.
+        StringTable { data: vec![0] }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.data.len().try_into().unwrap();
+
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+fn pad_to(data: &mut Vec<u8>, align: usize) {
+    data.resize((data.len() + align - 1) / align * align, 0);
+}
+
+fn sym_len(format: Format) -> u64 {
+    match format.class {
+        ElfClass::Class32 => 0x10,
+        ElfClass::Class64 => 0x18,
+    }
+}
+
+fn word_align(format: Format) -> u64 {
+    match format.class {
+        ElfClass::Class32 => 4,
+        ElfClass::Class64 => 8,
+    }
+}
+
+fn write_sym<T: Write>(
+    buf: &mut T,
+    format: Format,
+    name: u32,
+    symbol: &Symbol,
+    shndx: u16,
+) -> Result<(), Error> {
+    let info = (STB_GLOBAL << 4) | u8::from(symbol.r#type);
+
+    write_u32(buf, format, name)?;
+
+    match format.class {
+        ElfClass::Class32 => {
+            write_u32(buf, format, symbol.addr.try_into()?)?;
+            write_u32(buf, format, symbol.size.try_into()?)?;
+            buf.write_all(&[info, 0])?;
+            write_u16(buf, format, shndx)?;
+        }
+        ElfClass::Class64 => {
+            buf.write_all(&[info, 0])?;
+            write_u16(buf, format, shndx)?;
+            write_u64(buf, format, symbol.addr)?;
+            write_u64(buf, format, symbol.size)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_shdr<T: Write>(buf: &mut T, format: Format, shdr: &SectionHeader) -> Result<(), Error> {
+    write_u32(buf, format, shdr.name)?;
+    write_u32(buf, format, shdr.sh_type)?;
+    write_native(buf, format, shdr.flags)?;
+    write_native(buf, format, shdr.addr)?;
+    write_native(buf, format, shdr.offset)?;
+    write_native(buf, format, shdr.size)?;
+    write_u32(buf, format, shdr.link)?;
+    write_u32(buf, format, shdr.info)?;
+    write_native(buf, format, shdr.align)?;
+    write_native(buf, format, shdr.entsize)?;
+
+    Ok(())
+}
+
+/// Build the section headers and the data of the symbol table, string tables and notes.
+///
+/// Each segment gets a section (and another `SHT_NOBITS` section for its zero-filled end), so
+/// symbols can refer to the section they point into. `data_start` is the file offset where the
+/// data of the returned sections will be written.
+fn sections(
+    format: Format,
+    segments: &[Segment],
+    data_offsets: &[u64],
+    data_start: u64,
+    debug: &DebugInfo,
+) -> Result<Sections, Error> {
+    let mut shstrtab = StringTable::new();
+    let mut headers = vec![SectionHeader::default()];
+    let mut data = vec![];
+
+    for (i, (segment, &offset)) in segments.iter().zip(data_offsets).enumerate() {
+        let filesz: u64 = segment.data.len().try_into()?;
+        let section = SectionHeader {
+            flags: SHF_ALLOC
+                | if segment.perm.write { SHF_WRITE } else { 0 }
+                | if segment.perm.execute {
+                    SHF_EXECINSTR
+                } else {
+                    0
+                },
+            align: 1,
+            ..SectionHeader::default()
+        };
+
+        if filesz > 0 {
+            headers.push(SectionHeader {
+                name: shstrtab.add(&format!(".load{}", i)),
+                sh_type: SHT_PROGBITS,
+                addr: segment.paddr,
+                offset,
+                size: filesz,
+                ..section.clone()
+            });
+        }
+
+        if segment.memsz > filesz {
+            headers.push(SectionHeader {
+                name: shstrtab.add(&format!(".bss{}", i)),
+                sh_type: SHT_NOBITS,
+                addr: segment.paddr + filesz,
+                offset: offset + filesz,
+                size: segment.memsz - filesz,
+                ..section
+            });
+        }
+    }
+
+    let symtab_idx = headers.len();
+    let strtab_idx = symtab_idx + 1;
+    let mut strtab = StringTable::new();
+    let mut symtab = vec![];
+
+    pad_to(&mut data, word_align(format).try_into()?);
+
+    // The first symbol is always the undefined one.
+    symtab.resize(sym_len(format).try_into()?, 0);
+
+    for symbol in &debug.symbols {
+        let shndx = match headers.iter().position(|h| h.contains(symbol.addr)) {
+            Some(idx) => idx.try_into()?,
+            None => SHN_ABS,
+        };
+
+        write_sym(&mut symtab, format, strtab.add(&symbol.name), symbol, shndx)?;
+    }
+
+    headers.push(SectionHeader {
+        name: shstrtab.add(".symtab"),
+        sh_type: SHT_SYMTAB,
+        offset: data_start + u64::try_from(data.len())?,
+        size: symtab.len().try_into()?,
+        link: strtab_idx.try_into()?,
+        // The index of the first non-local symbol.
+        info: 1,
+        align: word_align(format),
+        entsize: sym_len(format),
+        ..SectionHeader::default()
+    });
+    data.extend(symtab);
+
+    headers.push(SectionHeader {
+        name: shstrtab.add(".strtab"),
+        sh_type: SHT_STRTAB,
+        offset: data_start + u64::try_from(data.len())?,
+        size: strtab.data.len().try_into()?,
+        align: 1,
+        ..SectionHeader::default()
+    });
+    data.extend(strtab.data);
+
+    let mut note = vec![];
+    let mut desc = debug.system_name.as_bytes().to_vec();
+
+    desc.push(0);
+    write_u32(&mut note, format, NOTE_NAME.len().try_into()?)?;
+    write_u32(&mut note, format, desc.len().try_into()?)?;
+    write_u32(&mut note, format, NT_EPOXY_SYSTEM_NAME)?;
+    note.extend_from_slice(NOTE_NAME);
+    pad_to(&mut note, 4);
+    note.extend(desc);
+    pad_to(&mut note, 4);
+
+    pad_to(&mut data, 4);
+    headers.push(SectionHeader {
+        name: shstrtab.add(".note.epoxy"),
+        sh_type: SHT_NOTE,
+        offset: data_start + u64::try_from(data.len())?,
+        size: note.len().try_into()?,
+        align: 4,
+        ..SectionHeader::default()
+    });
+    data.extend(note);
+
+    let shstrndx = headers.len();
+    let name = shstrtab.add(".shstrtab");
+
+    headers.push(SectionHeader {
+        name,
+        sh_type: SHT_STRTAB,
+        offset: data_start + u64::try_from(data.len())?,
+        size: shstrtab.data.len().try_into()?,
+        align: 1,
+        ..SectionHeader::default()
+    });
+    data.extend(shstrtab.data);
+
+    // The section headers follow the data.
+    pad_to(&mut data, word_align(format).try_into()?);
+
+    Ok(Sections {
+        headers,
+        data,
+        shstrndx,
     })
 }
 
+fn write_u16<T: Write>(buf: &mut T, format: Format, value: u16) -> Result<(), Error> {
+    match format.endianness {
+        Endianness::Little => buf.write_u16::<LittleEndian>(value)?,
+        Endianness::Big => buf.write_u16::<BigEndian>(value)?,
+    }
+
+    Ok(())
+}
+
+fn write_u32<T: Write>(buf: &mut T, format: Format, value: u32) -> Result<(), Error> {
+    match format.endianness {
+        Endianness::Little => buf.write_u32::<LittleEndian>(value)?,
+        Endianness::Big => buf.write_u32::<BigEndian>(value)?,
+    }
+
+    Ok(())
+}
+
+fn write_u64<T: Write>(buf: &mut T, format: Format, value: u64) -> Result<(), Error> {
+    match format.endianness {
+        Endianness::Little => buf.write_u64::<LittleEndian>(value)?,
+        Endianness::Big => buf.write_u64::<BigEndian>(value)?,
+    }
+
+    Ok(())
+}
+
+/// Write a value with the size of a machine word.
+fn write_native<T: Write>(buf: &mut T, format: Format, value: u64) -> Result<(), Error> {
+    match format.class {
+        ElfClass::Class32 => write_u32(buf, format, value.try_into()?),
+        ElfClass::Class64 => write_u64(buf, format, value),
+    }
+}
+
 fn ehdr_len(format: Format) -> u64 {
-    match format {
-        Format::Elf32 => 0x34,
-        Format::Elf64 => 0x40,
+    match format.class {
+        ElfClass::Class32 => 0x34,
+        ElfClass::Class64 => 0x40,
     }
 }
 
 fn phdr_len(format: Format) -> u64 {
-    match format {
-        Format::Elf32 => 0x20,
-        Format::Elf64 => 0x38,
+    match format.class {
+        ElfClass::Class32 => 0x20,
+        ElfClass::Class64 => 0x38,
     }
 }
 
 fn shdr_len(format: Format) -> u64 {
-    match format {
-        Format::Elf32 => 0x28,
-        Format::Elf64 => 0x40,
+    match format.class {
+        ElfClass::Class32 => 0x28,
+        ElfClass::Class64 => 0x40,
     }
 }
 
 /// The offset at which the segment data is serialized into the resulting ELF. This is right after
 /// all headers.
-fn data_start(format: Format, chunks: &[Chunk]) -> u64 {
-    let clen: u64 = chunks.len().try_into().unwrap();
+fn data_start(format: Format, segments: &[Segment]) -> u64 {
+    let clen: u64 = segments.len().try_into().unwrap();
 
     ehdr_len(format) + phdr_len(format) * clen
 }
@@ -93,15 +519,21 @@ fn write_ehdr<T: Write>(
     format: Format,
     entry: u64,
     phdr_count: usize,
+    shoff: u64,
+    shdr_count: usize,
+    shstrndx: usize,
 ) -> Result<(), Error> {
-    buf.write_u32::<BigEndian>(0x7F454c46)?; // Magic
+    buf.write_u32::<BigEndian>(0x7f454c46)?; // Magic
 
     buf.write_all(&[
-        match format {
-            Format::Elf32 => 1,
-            Format::Elf64 => 2,
+        match format.class {
+            ElfClass::Class32 => 1,
+            ElfClass::Class64 => 2,
+        },
+        match format.endianness {
+            Endianness::Little => 1,
+            Endianness::Big => 2,
         },
-        1, // Little-Endian
         1, // Version
         0, // System-V ABI
     ])?;
@@ -110,92 +542,406 @@ fn write_ehdr<T: Write>(
 
     // The fields below use the endianness specified above.
 
-    buf.write_u16::<LittleEndian>(2)?; // Exectuable
-    buf.write_u16::<LittleEndian>(0xf3)?; // Machine: RISC-V
-    buf.write_u32::<LittleEndian>(1)?; // Version
+    write_u16(buf, format, 2)?; // Exectuable
+    write_u16(buf, format, format.machine)?;
+    write_u32(buf, format, 1)?; // Version
 
     write_native(buf, format, entry)?;
     write_native(buf, format, ehdr_len(format))?; // Start of Phdrs
-    write_native(buf, format, 0)?; // Start of Shdrs (we have none)
+    write_native(buf, format, shoff)?; // Start of Shdrs
 
-    buf.write_u32::<LittleEndian>(0)?; // Flags
-    buf.write_u16::<LittleEndian>(ehdr_len(format).try_into()?)?;
-    buf.write_u16::<LittleEndian>(phdr_len(format).try_into()?)?;
+    write_u32(buf, format, 0)?; // Flags
+    write_u16(buf, format, ehdr_len(format).try_into()?)?;
+    write_u16(buf, format, phdr_len(format).try_into()?)?;
 
-    buf.write_u16::<LittleEndian>(phdr_count.try_into()?)?;
-    buf.write_u16::<LittleEndian>(shdr_len(format).try_into()?)?;
+    write_u16(buf, format, phdr_count.try_into()?)?;
+    write_u16(buf, format, shdr_len(format).try_into()?)?;
 
-    buf.write_u16::<LittleEndian>(0)?; // Shdr count
-    buf.write_u16::<LittleEndian>(0)?; // stridx
+    write_u16(buf, format, shdr_count.try_into()?)?;
+    write_u16(buf, format, shstrndx.try_into()?)?;
 
     Ok(())
 }
 
+fn p_flags(perm: Permissions) -> u32 {
+    (if perm.read { PF_R } else { 0 })
+        | (if perm.write { PF_W } else { 0 })
+        | (if perm.execute { PF_X } else { 0 })
+}
+
+/// Returns the first file offset at or after `pos` that is congruent to `paddr` modulo the page
+/// size.
+fn aligned_offset(pos: u64, paddr: u64) -> u64 {
+    pos + (paddr % PAGE_SIZE + PAGE_SIZE - pos % PAGE_SIZE) % PAGE_SIZE
+}
+
 fn write_phdr<T: Write>(
     buf: &mut T,
     format: Format,
     offset: u64,
-    chunk: &Chunk,
+    segment: &Segment,
 ) -> Result<(), Error> {
-    buf.write_u32::<LittleEndian>(1)?; // PT_LOAD
+    write_u32(buf, format, 1)?; // PT_LOAD
 
-    if let Format::Elf64 = format {
-        buf.write_u32::<LittleEndian>(7)?; // RWX
+    if format.class == ElfClass::Class64 {
+        write_u32(buf, format, p_flags(segment.perm))?;
     }
 
     write_native(buf, format, offset)?; // file offset
-    write_native(buf, format, chunk.paddr)?; // vaddr (ignored)
-    write_native(buf, format, chunk.paddr)?; // paddr
+    write_native(buf, format, segment.paddr)?; // vaddr (ignored)
+    write_native(buf, format, segment.paddr)?; // paddr
+    write_native(buf, format, segment.data.len().try_into()?)?; // file size
+    write_native(buf, format, segment.memsz)?; // memory size
 
-    // TODO It would be nice to check how many zeroes are at the end of the chunk and optimize for
-    // file size here.
-    write_native(buf, format, chunk.data.len().try_into()?)?; // file size
-    write_native(buf, format, chunk.data.len().try_into()?)?; // memory size
-
-    if let Format::Elf32 = format {
-        buf.write_u32::<LittleEndian>(7)?; // RWX (ignored)
+    if format.class == ElfClass::Class32 {
+        write_u32(buf, format, p_flags(segment.perm))?;
     }
 
-    write_native(buf, format, 1)?; // Alignment
+    write_native(buf, format, PAGE_SIZE)?; // Alignment
 
     Ok(())
 }
 
+/// Write physical memory as ELF file. With `debug`, the file also contains section headers and
+/// debug information.
 pub fn write<T: Write>(
     buf: &mut T,
     format: Format,
     entry: u64,
     pmem: &PhysMemory,
+    debug: Option<&DebugInfo>,
 ) -> Result<(), Error> {
     let chunks = pmem.chunks();
+    let segments = segments(&chunks);
 
-    write_ehdr(buf, format, entry, chunks.len())?;
-
-    // The size of all data blocks.
-    let chunk_sizes = chunks
-        .iter()
-        .map(|c| -> u64 { c.data.len().try_into().unwrap() });
+    // The offset of each data block in the file and the end of all data.
+    let (data_offsets, data_end) = segments.iter().fold(
+        (vec![], data_start(format, &segments)),
+        |(mut offsets, pos), s| {
+            let offset = aligned_offset(pos, s.paddr);
 
-    // The offset of each data block in the file. The last element of this vector is unused, it
-    // points to after the last memory block.
-    let data_offsets = chunk_sizes
-        .clone()
-        .fold(vec![data_start(format, &chunks)], |mut acc, s| {
-            acc.push(acc.last().unwrap() + s);
-            acc
-        });
+            offsets.push(offset);
+            (offsets, offset + u64::try_from(s.data.len()).unwrap())
+        },
+    );
+
+    let sections = debug
+        .map(|d| sections(format, &segments, &data_offsets, data_end, d))
+        .transpose()?;
+
+    match &sections {
+        Some(s) => write_ehdr(
+            buf,
+            format,
+            entry,
+            segments.len(),
+            data_end + u64::try_from(s.data.len())?,
+            s.headers.len(),
+            s.shstrndx,
+        )?,
+        None => write_ehdr(buf, format, entry, segments.len(), 0, 0, 0)?,
+    }
 
     // Write all PHDRs.
-    for (&off, chunk) in data_offsets.iter().zip(chunks.iter()) {
+    for (&off, segment) in data_offsets.iter().zip(segments.iter()) {
         debug!(
-            "Writing chunk for paddr {:x} at file offset {:#x}",
-            chunk.paddr, off
+            "Writing segment for paddr {:x} ({:#x} bytes in file, {:#x} in memory) at file offset {:#x}",
+            segment.paddr,
+            segment.data.len(),
+            segment.memsz,
+            off
         );
-        write_phdr(buf, format, off, chunk)?;
+        write_phdr(buf, format, off, segment)?;
+    }
+
+    // Write all payload data with the padding in between.
+    let mut pos = data_start(format, &segments);
+
+    for (&off, segment) in data_offsets.iter().zip(segments.iter()) {
+        buf.write_all(&vec![0; (off - pos).try_into()?])?;
+        buf.write_all(segment.data)?;
+
+        pos = off + u64::try_from(segment.data.len())?;
     }
 
-    // Write all payload data.
-    buf.write_all(&chunks.iter().map(|c| &c.data).cloned().concat())?;
+    if let Some(sections) = sections {
+        buf.write_all(&sections.data)?;
+
+        for shdr in &sections.headers {
+            write_shdr(buf, format, shdr)?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_ptr_alloc::BumpPointerAlloc;
+    use crate::interval::Interval;
+    use crate::phys_mem::PlaceAs;
+
+    fn riscv(class: ElfClass) -> Format {
+        Format {
+            class,
+            endianness: Endianness::Little,
+            machine: crate::elf::EM_RISCV,
+        }
+    }
+
+    fn chunk(paddr: u64, data: Vec<u8>) -> Chunk {
+        Chunk {
+            paddr,
+            data,
+            perm: Permissions::all(),
+        }
+    }
+
+    #[test]
+    fn test_zero_runs() {
+        assert_eq!(zero_runs(&[], 1), vec![]);
+        assert_eq!(
+            zero_runs(&[1, 0, 0, 2, 0, 3, 0, 0], 2),
+            vec![(1, 3), (6, 8)]
+        );
+        assert_eq!(zero_runs(&[0, 1], 1), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_chunk_segments() {
+        // Short trailing zeroes are moved out of the file.
+        let c = chunk(0x1000, vec![1, 2, 0, 0]);
+        assert_eq!(
+            chunk_segments(&c),
+            vec![Segment {
+                paddr: 0x1000,
+                data: &[1, 2],
+                memsz: 4,
+                perm: Permissions::all(),
+            }]
+        );
+
+        // Chunks with only zeroes need no file data at all.
+        let c = chunk(0x1000, vec![0; 16]);
+        assert_eq!(
+            chunk_segments(&c),
+            vec![Segment {
+                paddr: 0x1000,
+                data: &[],
+                memsz: 16,
+                perm: Permissions::all(),
+            }]
+        );
+
+        // Large runs of zeroes split a chunk, small ones don't.
+        let data = [
+            vec![1, 0, 2],
+            vec![0; MIN_ZERO_GAP],
+            vec![3],
+            vec![0; MIN_ZERO_GAP + 1],
+        ]
+        .concat();
+        let c = chunk(0x1000, data);
+        assert_eq!(
+            chunk_segments(&c),
+            vec![
+                Segment {
+                    paddr: 0x1000,
+                    data: &[1, 0, 2],
+                    memsz: 3 + MIN_ZERO_GAP as u64,
+                    perm: Permissions::all(),
+                },
+                Segment {
+                    paddr: 0x1003 + MIN_ZERO_GAP as u64,
+                    data: &[3],
+                    memsz: 2 + MIN_ZERO_GAP as u64,
+                    perm: Permissions::all(),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let mut pmem = PhysMemory::new(
+            std::iter::once(BumpPointerAlloc::new(
+                Interval::new_with_size(0x8000_0000, 0x10_0000),
+                PAGE_SIZE,
+            ))
+            .collect(),
+        );
+        let mut out = vec![];
+
+        let read_execute = Permissions {
+            write: false,
+            ..Permissions::all()
+        };
+
+        pmem.place(&[0x13; 0x1000], PlaceAs::Shareable, read_execute)
+            .unwrap();
+        pmem.place(
+            &[[1].as_ref(), &[0; 0x10000], &[2, 0]].concat(),
+            PlaceAs::Unique,
+            Permissions::read_write(),
+        )
+        .unwrap();
+
+        write(&mut out, riscv(ElfClass::Class64), 0x8000_0000, &pmem, None).unwrap();
+
+        let elf = goblin::elf::Elf::parse(&out).unwrap();
+        let loads: Vec<_> = elf
+            .program_headers
+            .iter()
+            .map(|ph| (ph.p_paddr, ph.p_filesz, ph.p_memsz, ph.p_flags))
+            .collect();
+
+        assert_eq!(
+            loads,
+            vec![
+                (0x8000_0000, 0x1000, 0x1000, PF_R | PF_X),
+                (0x8000_1000, 1, 0x10001, PF_R | PF_W),
+                (0x8001_1001, 1, 2, PF_R | PF_W)
+            ]
+        );
+
+        for ph in &elf.program_headers {
+            assert_eq!(ph.p_align, PAGE_SIZE);
+            assert_eq!(ph.p_offset % PAGE_SIZE, ph.p_paddr % PAGE_SIZE);
+        }
+
+        assert_eq!(out[elf.program_headers[1].p_offset as usize], 1);
+        assert_eq!(out[elf.program_headers[2].p_offset as usize], 2);
+        assert!(out.len() < 0x4000);
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+        let mut out = vec![];
+
+        pmem.write(0x8000_0000, &[1, 2, 3, 4, 0, 0, 0, 0]);
+
+        let debug = DebugInfo {
+            system_name: "test-system",
+            symbols: vec![
+                Symbol {
+                    name: "_start".to_string(),
+                    addr: 0x8000_0000,
+                    size: 4,
+                    r#type: SymbolType::Func,
+                },
+                Symbol {
+                    name: "zeroes".to_string(),
+                    addr: 0x8000_0004,
+                    size: 4,
+                    r#type: SymbolType::Object,
+                },
+                Symbol {
+                    name: "elsewhere".to_string(),
+                    addr: 0x1000,
+                    size: 0,
+                    r#type: SymbolType::NoType,
+                },
+            ],
+        };
+
+        for &format in &[riscv(ElfClass::Class32), riscv(ElfClass::Class64)] {
+            out.clear();
+            write(&mut out, format, 0x8000_0000, &pmem, Some(&debug)).unwrap();
+
+            let elf = goblin::elf::Elf::parse(&out).unwrap();
+            let section_names = elf
+                .section_headers
+                .iter()
+                .map(|sh| elf.shdr_strtab.get(sh.sh_name).unwrap().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                section_names,
+                vec![
+                    "",
+                    ".load0",
+                    ".bss0",
+                    ".symtab",
+                    ".strtab",
+                    ".note.epoxy",
+                    ".shstrtab"
+                ]
+            );
+
+            let symbols = elf
+                .syms
+                .iter()
+                .skip(1)
+                .map(|s| {
+                    (
+                        elf.strtab.get(s.st_name).unwrap().unwrap(),
+                        s.st_value,
+                        s.st_shndx,
+                        s.st_type(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                symbols,
+                vec![
+                    ("_start", 0x8000_0000, 1, goblin::elf::sym::STT_FUNC),
+                    ("zeroes", 0x8000_0004, 2, goblin::elf::sym::STT_OBJECT),
+                    (
+                        "elsewhere",
+                        0x1000,
+                        SHN_ABS.into(),
+                        goblin::elf::sym::STT_NOTYPE
+                    ),
+                ]
+            );
+
+            let notes = elf
+                .iter_note_sections(&out, Some(".note.epoxy"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].name, "Epoxy");
+            assert_eq!(notes[0].n_type, NT_EPOXY_SYSTEM_NAME);
+            assert_eq!(notes[0].desc, b"test-system\0");
+        }
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+        let mut out = vec![];
+
+        pmem.write(0x8000_0000, &[1, 2, 3, 4]);
+
+        let debug = DebugInfo {
+            system_name: "test-system",
+            symbols: vec![Symbol {
+                name: "_start".to_string(),
+                addr: 0x8000_0000,
+                size: 4,
+                r#type: SymbolType::Func,
+            }],
+        };
+        let format = Format {
+            class: ElfClass::Class32,
+            endianness: Endianness::Big,
+            machine: goblin::elf::header::EM_MIPS,
+        };
+
+        write(&mut out, format, 0x8000_0000, &pmem, Some(&debug)).unwrap();
+
+        let elf = goblin::elf::Elf::parse(&out).unwrap();
+
+        assert!(!elf.little_endian);
+        assert_eq!(elf.header.e_machine, goblin::elf::header::EM_MIPS);
+        assert_eq!(elf.entry, 0x8000_0000);
+        assert_eq!(elf.program_headers[0].p_paddr, 0x8000_0000);
+        assert_eq!(elf.program_headers[0].p_filesz, 4);
+        assert_eq!(elf.syms.get(1).unwrap().st_value, 0x8000_0000);
+    }
+}
@@ -3,7 +3,7 @@
 use anyhow::{Context, Error};
 use clap::{App, AppSettings, Arg, SubCommand};
 use log::{debug, info};
-use serde_dhall;
+use std::convert::{TryFrom, TryInto};
 use std::path::Path;
 
 use crate::boot_image;
@@ -11,10 +11,15 @@ use crate::bump_ptr_alloc::{BumpPointerAlloc, SimpleAlloc};
 use crate::cfgfile;
 use crate::cfgtypes;
 use crate::codegen;
+use crate::config_blob;
 use crate::constants::*;
+use crate::devicetree;
+use crate::fdt::Fdt;
 use crate::interval::Interval;
 use crate::kernel_codegen;
+use crate::output;
 use crate::runtypes;
+use crate::schedulability;
 
 /// Flatten a nested result.
 ///
@@ -30,7 +35,12 @@ fn make_anon_mem<T: SimpleAlloc>(
     valloc: &mut T,
     size: u64,
 ) -> Result<runtypes::VirtualMemoryRegion, Error> {
-    assert_eq!(size % PAGE_SIZE, 0);
+    if size % PAGE_SIZE != 0 {
+        return Err(format_err!(
+            "Anonymous memory size {:#x} is not a multiple of the page size",
+            size
+        ));
+    }
 
     Ok(runtypes::VirtualMemoryRegion {
         virt_start: valloc
@@ -40,20 +50,67 @@ fn make_anon_mem<T: SimpleAlloc>(
     })
 }
 
-fn make_user_stack<T: SimpleAlloc>(valloc: &mut T) -> Result<runtypes::VirtualMemoryRegion, Error> {
+/// Reserve an unmapped page that catches accesses beyond the memory next to it.
+fn make_guard_page<T: SimpleAlloc>(valloc: &mut T) -> Result<(), Error> {
     valloc
         .alloc(PAGE_SIZE)
-        .ok_or_else(|| format_err!("Failed to allocate stack guard page"))?;
+        .ok_or_else(|| format_err!("Failed to allocate guard page"))?;
 
-    let stack = make_anon_mem(valloc, USER_STACK_SIZE)?;
+    Ok(())
+}
 
-    valloc
-        .alloc(PAGE_SIZE)
-        .ok_or_else(|| format_err!("Failed to allocate stack guard page"))?;
+/// Convert a size in KiB from the configuration of an application to bytes.
+fn page_aligned_size(program: &cfgtypes::Application, what: &str, kb: u64) -> Result<u64, Error> {
+    kb.checked_mul(1024)
+        .filter(|&size| size != 0 && size % PAGE_SIZE == 0)
+        .ok_or_else(|| {
+            format_err!(
+                "{} size of application {} must be a non-zero multiple of {} KiB, but is {} KiB",
+                what,
+                program.name,
+                PAGE_SIZE >> 10,
+                kb
+            )
+        })
+}
+
+/// Return the stack size of an application in bytes.
+fn stack_size(program: &cfgtypes::Application) -> Result<u64, Error> {
+    page_aligned_size(program, "Stack", program.stack_kb)
+}
+
+/// Return the size of the thread-local storage block of an application in bytes.
+fn tls_size(program: &cfgtypes::Application) -> Result<u64, Error> {
+    page_aligned_size(program, "TLS", program.tls_kb)
+}
+
+/// Allocate a stack surrounded by guard pages.
+fn make_stack<T: SimpleAlloc>(
+    valloc: &mut T,
+    size: u64,
+) -> Result<runtypes::VirtualMemoryRegion, Error> {
+    make_guard_page(valloc)?;
+    let stack = make_anon_mem(valloc, size)?;
+    make_guard_page(valloc)?;
 
     Ok(stack)
 }
 
+/// Allocate a stack and the thread-local storage block directly above it. Both are surrounded by
+/// guard pages, so the stack grows away from the TLS block.
+fn make_stack_with_tls<T: SimpleAlloc>(
+    valloc: &mut T,
+    stack_size: u64,
+    tls_size: u64,
+) -> Result<(runtypes::VirtualMemoryRegion, runtypes::VirtualMemoryRegion), Error> {
+    make_guard_page(valloc)?;
+    let stack = make_anon_mem(valloc, stack_size)?;
+    let tls = make_anon_mem(valloc, tls_size)?;
+    make_guard_page(valloc)?;
+
+    Ok((stack, tls))
+}
+
 fn map_memory<T: SimpleAlloc>(
     valloc: &mut T,
     region: &cfgtypes::MemoryRegion,
@@ -147,20 +204,143 @@ fn to_process_resources<T: SimpleAlloc>(
     Ok(rmap)
 }
 
+/// Resolve the initial values of the argument registers of a process' thread.
+fn initial_regs(
+    args: &[cfgtypes::Argument],
+    pid: u64,
+    heap_start: u64,
+    heap_end: u64,
+    config: Option<u64>,
+) -> Result<[u64; ARG_REGISTERS], Error> {
+    if args.len() > ARG_REGISTERS {
+        return Err(format_err!(
+            "Too many arguments: {} given, but only {} argument registers exist",
+            args.len(),
+            ARG_REGISTERS
+        ));
+    }
+
+    let mut regs = [0; ARG_REGISTERS];
+
+    for (reg, arg) in regs.iter_mut().zip(args) {
+        *reg = match arg {
+            cfgtypes::Argument::Value(v) => *v,
+            cfgtypes::Argument::ProcessIndex => pid,
+            cfgtypes::Argument::HeapStart => heap_start,
+            cfgtypes::Argument::HeapEnd => heap_end,
+            cfgtypes::Argument::ConfigBlob => config.ok_or_else(|| {
+                format_err!("Configuration address requested, but process has no configuration")
+            })?,
+        };
+    }
+
+    Ok(regs)
+}
+
+/// Validate scheduling parameters and resolve them into timer ticks.
+fn sched_params(
+    sched: &cfgtypes::Scheduling,
+    timer_freq_hz: u64,
+) -> Result<runtypes::SchedParams, Error> {
+    let priority = u8::try_from(sched.priority)
+        .map_err(|_| format_err!("Priority {} is out of range (0-255)", sched.priority))?;
+    let time_slice = sched
+        .time_slice
+        .unwrap_or(timer_freq_hz / DEFAULT_SCHEDULE_HZ);
+    let min_time_slice = timer_freq_hz / MAX_SCHEDULE_HZ;
+
+    if time_slice == 0 || time_slice < min_time_slice {
+        return Err(format_err!(
+            "Time slice of {} ticks is too short for a {} Hz timer (at least {} ticks)",
+            time_slice,
+            timer_freq_hz,
+            std::cmp::max(min_time_slice, 1)
+        ));
+    }
+
+    if let Some(budget) = &sched.budget {
+        if budget.budget == 0 || budget.budget > budget.period {
+            return Err(format_err!(
+                "Budget of {} ticks does not fit into a period of {} ticks",
+                budget.budget,
+                budget.period
+            ));
+        }
+
+        if time_slice > budget.budget {
+            return Err(format_err!(
+                "Time slice of {} ticks exceeds the budget of {} ticks",
+                time_slice,
+                budget.budget
+            ));
+        }
+    }
+
+    Ok(runtypes::SchedParams {
+        priority,
+        time_slice,
+        budget: sched.budget.map(|b| runtypes::Budget {
+            budget: b.budget,
+            period: b.period,
+        }),
+    })
+}
+
+/// Return the index of a process in the kernel's process list. The kernel orders processes by name.
+fn process_index(system: &cfgtypes::System, name: &str) -> u64 {
+    system
+        .processes
+        .iter()
+        .filter(|p| p.name.as_str() < name)
+        .count()
+        .try_into()
+        .unwrap()
+}
+
+/// Turn the configuration record of a process instance into a blob and map it read-only.
+fn make_instance_config<T: SimpleAlloc>(
+    valloc: &mut T,
+    config: &cfgtypes::Config,
+) -> Result<runtypes::InstanceConfig, Error> {
+    let mut data = config_blob::serialize(config)?;
+    let size = (u64::try_from(data.len())? + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+    data.resize(size.try_into()?, 0);
+
+    Ok(runtypes::InstanceConfig {
+        value: config.clone(),
+        region: runtypes::VirtualMemoryRegion {
+            virt_start: valloc
+                .alloc(size)
+                .ok_or_else(|| format_err!("Failed to allocate instance configuration"))?,
+            phys: runtypes::MemoryRegion::ReadOnlyData { data },
+        },
+    })
+}
+
 #[derive(Debug, Clone, Copy)]
 enum ProcessType {
-    Kernel,
-    User,
+    Kernel {
+        /// The number of harts that each need a kernel stack.
+        harts: u64,
+    },
+    User {
+        /// The index of the process in the kernel's process list.
+        pid: u64,
+
+        /// The frequency of the timer that drives scheduling.
+        timer_freq_hz: u64,
+    },
 }
 
 fn get_process_valloc(process_type: ProcessType) -> impl SimpleAlloc {
     BumpPointerAlloc::new(
         match process_type {
-            ProcessType::User => Interval {
+            ProcessType::User { .. } => Interval {
                 from: USER_RESOURCE_START,
                 to: USER_RESOURCE_END,
             },
-            ProcessType::Kernel => Interval {
+            ProcessType::Kernel { .. } => Interval {
                 from: KERN_RESOURCE_START,
                 to: KERN_RESOURCE_END,
             },
@@ -196,34 +376,125 @@ fn internalize_process(
         &machine.devices,
     )
     .context("Failed to resolve process resources for process")?;
+    let stack_bytes = stack_size(&program)?;
 
     Ok(match process_type {
-        ProcessType::User => {
-            let stack = make_user_stack(&mut valloc)?;
+        ProcessType::User { pid, timer_freq_hz } => {
+            if let Some(base) = process.load_base.filter(|b| b % PAGE_SIZE != 0) {
+                return Err(format_err!(
+                    "Load base {:#x} of process {} is not page-aligned",
+                    base,
+                    process.name
+                ));
+            }
+
+            let (stack, tls) = make_stack_with_tls(&mut valloc, stack_bytes, tls_size(&program)?)?;
             let heap = make_anon_mem(&mut valloc, program.heap_kb << 10)?;
+            let heap_start = heap.virt_start;
+            let heap_end = heap.virt_start + heap.size();
+            let config = process
+                .config
+                .as_ref()
+                .map(|c| make_instance_config(&mut valloc, c))
+                .transpose()
+                .with_context(|| format!("Failed to configure process {}", process.name))?;
 
             runtypes::Process {
                 name: process.name.clone(),
                 binary: format!("bin/{}", process.name),
                 stack_ptr: stack.virt_start + stack.size() - 8,
-                heap_start: heap.virt_start,
-                heap_end: heap.virt_start + heap.size(),
+                initial_regs: initial_regs(
+                    &process.args,
+                    pid,
+                    heap_start,
+                    heap_end,
+                    config.as_ref().map(|c| c.region.virt_start),
+                )
+                .with_context(|| {
+                    format!("Failed to set up registers of process {}", process.name)
+                })?,
+                sched: sched_params(&process.sched, timer_freq_hz).with_context(|| {
+                    format!("Invalid scheduling parameters for process {}", process.name)
+                })?,
+                hart: process.hart,
+                load_base: process.load_base,
                 anon_mem: vec![stack, heap],
+                tls: Some(tls),
+                config,
                 resources,
             }
         }
-        ProcessType::Kernel => runtypes::Process {
+        ProcessType::Kernel { harts } => runtypes::Process {
             name: process.name.clone(),
             binary: format!("bin/{}", process.name),
             stack_ptr: 0,
-            heap_start: 0,
-            heap_end: 0,
-            anon_mem: vec![],
+            initial_regs: [0; ARG_REGISTERS],
+            sched: runtypes::SchedParams::default(),
+            hart: 0,
+            load_base: None,
+            anon_mem: (0..harts)
+                .map(|_| make_stack(&mut valloc, stack_bytes))
+                .collect::<Result<Vec<runtypes::VirtualMemoryRegion>, Error>>()
+                .context("Failed to allocate kernel stacks")?,
+            tls: None,
+            config: None,
             resources,
         },
     })
 }
 
+/// Check that processes are pinned to existing harts and that every hart has something to do,
+/// unless it is explicitly marked as idle. The boot hart can never be idle.
+fn check_harts(
+    harts: u64,
+    idle_harts: &[u64],
+    processes: &[runtypes::Process],
+) -> Result<(), Error> {
+    if harts == 0 {
+        return Err(format_err!("The machine needs at least one hart"));
+    }
+
+    if let Some(p) = processes.iter().find(|p| p.hart >= harts) {
+        return Err(format_err!(
+            "Process {} is pinned to hart {}, but the machine only has {} harts",
+            p.name,
+            p.hart,
+            harts
+        ));
+    }
+
+    if let Some(hart) = idle_harts.iter().find(|&&h| h >= harts) {
+        return Err(format_err!(
+            "Idle hart {} does not exist, the machine only has {} harts",
+            hart,
+            harts
+        ));
+    }
+
+    if !processes.iter().any(|p| p.hart == 0) {
+        return Err(format_err!("The boot hart (hart 0) has no processes"));
+    }
+
+    for hart in 0..harts {
+        let has_threads = processes.iter().any(|p| p.hart == hart);
+        let is_idle = idle_harts.contains(&hart);
+
+        if has_threads && is_idle {
+            return Err(format_err!(
+                "Hart {} is marked as idle, but has processes pinned to it",
+                hart
+            ));
+        } else if !has_threads && !is_idle {
+            return Err(format_err!(
+                "Hart {} has no processes. Mark it as idle if this is intentional.",
+                hart
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 /// Take a system description as it comes in from the config files and read all other configurations
 /// it references.
 fn configure_system(
@@ -235,25 +506,67 @@ fn configure_system(
             .parse()
             .context("Failed to parse machine description")?;
 
+    if machine.harts > MAX_HARTS {
+        return Err(format_err!(
+            "Machine {} has {} harts, but the kernel does not support SMP yet and only runs on {}",
+            machine.name,
+            machine.harts,
+            MAX_HARTS
+        ));
+    }
+
+    let kernel = internalize_process(
+        root,
+        &machine,
+        &cfgtypes::Process {
+            name: system.kernel.clone(),
+            program: system.kernel.clone(),
+            args: vec![],
+            config: None,
+            sched: cfgtypes::Scheduling::default(),
+            hart: 0,
+            load_base: None,
+        },
+        &system.mappings,
+        ProcessType::Kernel {
+            harts: machine.harts,
+        },
+    )?;
+
+    let timer_freq_hz = kernel
+        .resources
+        .values()
+        .find_map(|r| match r.meta {
+            runtypes::ResourceMetaInfo::SBITimer { freq_hz } => Some(freq_hz),
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("The kernel needs a SBITimer resource for scheduling"))?;
+
     let processes: Vec<runtypes::Process> = system
         .processes
         .iter()
-        .map(|p| internalize_process(root, &machine, p, &system.mappings, ProcessType::User))
+        .map(|p| {
+            internalize_process(
+                root,
+                &machine,
+                p,
+                &system.mappings,
+                ProcessType::User {
+                    pid: process_index(system, &p.name),
+                    timer_freq_hz,
+                },
+            )
+        })
         .collect::<Result<Vec<runtypes::Process>, Error>>()?;
 
+    check_harts(machine.harts, &system.idle_harts, &processes)?;
+
     Ok(runtypes::Configuration {
         name: system.name.clone(),
         available_memory: machine.available_memory.clone(),
-        kernel: internalize_process(
-            root,
-            &machine,
-            &cfgtypes::Process {
-                name: system.kernel.clone(),
-                program: system.kernel.clone(),
-            },
-            &system.mappings,
-            ProcessType::Kernel,
-        )?,
+        devices: machine.devices.clone(),
+        harts: machine.harts,
+        kernel,
         processes: processes
             .into_iter()
             .map(|p| -> (String, runtypes::Process) { (p.name.clone(), p) })
@@ -261,51 +574,177 @@ fn configure_system(
     })
 }
 
-fn epoxy_verify(system: &runtypes::Configuration) -> Result<(), Error> {
+/// Return the scheduling parameters of all threads on the given hart.
+fn hart_threads(system: &runtypes::Configuration, hart: u64) -> Vec<(&str, runtypes::SchedParams)> {
+    system
+        .processes
+        .values()
+        .filter(|p| p.hart == hart)
+        .map(|p| (p.name.as_str(), p.sched))
+        .collect()
+}
+
+/// Run the schedulability analysis for each hart and return the response times per hart. Threads
+/// only compete with threads on the same hart. Problems on all harts are reported at once.
+fn check_schedule(
+    system: &runtypes::Configuration,
+) -> Result<Vec<Vec<schedulability::ResponseTime>>, Error> {
+    let mut response_times = vec![];
+    let mut errors = vec![];
+
+    for hart in 0..system.harts {
+        match schedulability::analyze(&hart_threads(system, hart)) {
+            Ok(r) => response_times.push(r),
+            Err(e) => errors.push(format!(
+                "Threads on hart {} are not schedulable:\n{}",
+                hart, e
+            )),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(response_times)
+    } else {
+        Err(format_err!("{}", errors.join("\n")))
+    }
+}
+
+fn epoxy_verify(
+    system: &runtypes::Configuration,
+    user_binaries: Option<&Path>,
+) -> Result<(), Error> {
+    check_schedule(system)?;
+
+    if let Some(user_binaries) = user_binaries {
+        boot_image::check_binaries(system, user_binaries)?;
+    }
+
     info!("Everything is fine!");
     debug!("Resolved runtime configuration: {:#?}", system);
 
     Ok(())
 }
 
+fn epoxy_analyze_schedule(system: &runtypes::Configuration) -> Result<(), Error> {
+    for (hart, response_times) in (0..system.harts).zip(check_schedule(system)?) {
+        for r in response_times {
+            println!(
+                "{}: worst-case response time {} of {} ticks",
+                r.thread, r.response_time, r.deadline
+            );
+        }
+
+        println!(
+            "Budgeted threads use {}% of hart {}.",
+            schedulability::utilization_percent(&hart_threads(system, hart)),
+            hart
+        );
+    }
+
+    println!(
+        "This assumes that threads do not exceed their budgets. The kernel does not enforce them."
+    );
+
+    Ok(())
+}
+
+/// Write generated source code to a file or standard output.
+fn write_text(path: Option<&Path>, text: &str) -> Result<(), Error> {
+    output::write(path, false, |out| Ok(out.write_all(text.as_bytes())?))
+}
+
 fn epoxy_configure_process(
     system: &runtypes::Configuration,
     pname: &str,
     lang: &str,
+    path: Option<&Path>,
 ) -> Result<(), Error> {
     let process = system
         .processes
         .get(pname)
         .ok_or_else(|| format_err!("Failed to find processes {}", pname))?;
 
-    print!(
-        "{}",
-        codegen::generate(lang.parse::<codegen::Language>()?, &process)
-    );
+    write_text(
+        path,
+        &codegen::generate(lang.parse::<codegen::Language>()?, process),
+    )
+}
+
+/// Generate one or more kernel artifacts. If paths are given, there must be one for each type.
+fn epoxy_configure_kernel(
+    system: &runtypes::Configuration,
+    out_types: &[&str],
+    paths: &[&Path],
+) -> Result<(), Error> {
+    if !paths.is_empty() && paths.len() != out_types.len() {
+        return Err(format_err!(
+            "Got {} output types, but {} output paths",
+            out_types.len(),
+            paths.len()
+        ));
+    } else if paths.is_empty() && out_types.len() > 1 {
+        return Err(format_err!(
+            "Multiple output types need an output path each"
+        ));
+    }
+
+    for (i, out_type) in out_types.iter().enumerate() {
+        write_text(
+            paths.get(i).copied(),
+            &match *out_type {
+                "state-hpp" => kernel_codegen::generate_hpp(system)?,
+                "state-cpp" => kernel_codegen::generate_cpp(system)?,
+                "resources" => codegen::generate(codegen::Language::Cpp, &system.kernel),
+                _ => Err(format_err!(
+                    "Unrecognized output type. Should be one of: state-hpp state-cpp resources"
+                ))?,
+            },
+        )?;
+    }
 
     Ok(())
 }
 
-fn epoxy_configure_kernel(system: &runtypes::Configuration, out_type: &str) -> Result<(), Error> {
+fn epoxy_boot_image(
+    system: &runtypes::Configuration,
+    user_binaries: &Path,
+    dtb: Option<&Path>,
+    options: &boot_image::Options,
+    path: Option<&Path>,
+) -> Result<(), Error> {
+    if let Some(dtb) = dtb {
+        devicetree::check_machine(
+            &system.available_memory,
+            &system.devices,
+            &Fdt::from_file(dtb)?,
+        )
+        .context("Machine description does not match the device tree")?;
+    }
+
+    output::write(path, options.format.is_binary(), |out| {
+        boot_image::generate(system, user_binaries, options, out)
+    })
+}
+
+fn epoxy_import_dtb(dtb: &Path, name: Option<&str>) -> Result<(), Error> {
+    let fdt = Fdt::from_file(dtb)?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => dtb
+            .file_stem()
+            .ok_or_else(|| format_err!("Failed to derive machine name from {}", dtb.display()))?
+            .to_string_lossy()
+            .into_owned(),
+    };
+
     print!(
         "{}",
-        match out_type {
-            "state-hpp" => kernel_codegen::generate_hpp(&system)?,
-            "state-cpp" => kernel_codegen::generate_cpp(&system)?,
-            "resources" => codegen::generate(codegen::Language::CPP, &system.kernel),
-            _ => Err(format_err!(
-                "Unrecognized output type. Should be one of: state-hpp state-cpp resources"
-            ))?,
-        }
+        devicetree::to_dhall(&devicetree::import_machine(&name, &fdt)?)
     );
 
     Ok(())
 }
 
-fn epoxy_boot_image(system: &runtypes::Configuration, user_binaries: &Path) -> Result<(), Error> {
-    boot_image::generate(system, user_binaries)
-}
-
 pub fn main() -> Result<(), Error> {
     let matches = App::new("Epoxy Harden System Configuration")
         .arg(Arg::with_name("verbosity")
@@ -319,17 +758,19 @@ pub fn main() -> Result<(), Error> {
              .short("r")
              .long("cfg-root")
              .value_name("CFGROOT")
-             .required(true)
              .help("The directory where configuration files will be looked for."))
         .arg(Arg::with_name("system")
              .short("s")
              .long("system")
              .value_name("SYSTEM")
-             .required(true)
              .help("The system name that should be used. This should match a Dhall file in CFGROOT/systems."))
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .subcommand(SubCommand::with_name("verify")
-                    .about("Verify the system configuration"))
+                    .about("Verify the system configuration, including that all threads with a CPU budget meet their deadlines")
+                    .arg(Arg::with_name("user-binaries")
+                         .help("The path where user binaries can be found. If given, the binaries are checked against the kernel.")))
+        .subcommand(SubCommand::with_name("analyze-schedule")
+                    .about("Check that all threads with a CPU budget meet their deadlines, assuming budgets are enforced"))
         .subcommand(SubCommand::with_name("configure-process")
                     .about("Generate configuration code for one process")
                     .arg(Arg::with_name("process")
@@ -340,18 +781,65 @@ pub fn main() -> Result<(), Error> {
                          .short("l")
                          .long("language")
                          .value_name("LANG")
-                         .default_value("c++")))
+                         .default_value("c++"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .value_name("FILE")
+                         .help("Write the generated code to FILE instead of standard output")))
         .subcommand(SubCommand::with_name("configure-kernel")
                     .about("Generate configuration code for the kernel (C++ only)")
                     .arg(Arg::with_name("type")
                          .required(true)
+                         .multiple(true)
                          .help("Specify what type of output should be generated: state-hpp, state-cpp, or resources"))
-                    )
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .value_name("FILE")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Write the output to FILE instead of standard output. Give one per type.")))
         .subcommand(SubCommand::with_name("boot-image")
                     .about("Generate a bootable image for the target platform")
                     .arg(Arg::with_name("user-binaries")
                          .required(true)
-                         .help("The path where user binaries can be found")))
+                         .help("The path where user binaries can be found"))
+                    .arg(Arg::with_name("dtb")
+                         .long("dtb")
+                         .value_name("DTB")
+                         .help("A device tree of the target to check the machine description against"))
+                    .arg(Arg::with_name("generate-dtb")
+                         .long("generate-dtb")
+                         .help("Include a device tree for the system and patch its address into the kernel"))
+                    .arg(Arg::with_name("debug-info")
+                         .long("debug-info")
+                         .help("Add section headers and kernel symbols at their physical addresses to ELF images"))
+                    .arg(Arg::with_name("kernel-at-paddr")
+                         .long("kernel-at-paddr")
+                         .help("Place the kernel segments at the physical addresses in the kernel ELF"))
+                    .arg(Arg::with_name("format")
+                         .short("f")
+                         .long("format")
+                         .value_name("FORMAT")
+                         .default_value("elf")
+                         .help("The output format: elf, bin, ihex, srec, uimage, or fit"))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .value_name("FILE")
+                         .help("Write the boot image to FILE instead of standard output")))
+        .subcommand(SubCommand::with_name("import-dtb")
+                    .about("Generate a machine description from a flattened device tree")
+                    .arg(Arg::with_name("dtb")
+                         .value_name("DTB")
+                         .required(true)
+                         .help("The device tree blob to import"))
+                    .arg(Arg::with_name("name")
+                         .short("n")
+                         .long("name")
+                         .value_name("NAME")
+                         .help("The name of the machine. Defaults to the file name of the DTB.")))
         .get_matches();
 
     let verbose = matches.occurrences_of("verbosity") as usize;
@@ -363,15 +851,29 @@ pub fn main() -> Result<(), Error> {
         .init()
         .unwrap();
 
+    // Importing a device tree is the only subcommand that does not need a system description.
+    if let Some(import_matches) = matches.subcommand_matches("import-dtb") {
+        return epoxy_import_dtb(
+            Path::new(
+                import_matches
+                    .value_of("dtb")
+                    .expect("required option missing"),
+            ),
+            import_matches.value_of("name"),
+        );
+    }
+
     let cfg_root = Path::new(
         matches
             .value_of("cfg-root")
-            .expect("required option missing"),
+            .ok_or_else(|| format_err!("The configuration root (-r) is missing"))?,
     );
     let cfg_system = cfgfile::find(
         cfgfile::Type::System,
         cfg_root,
-        &matches.value_of("system").expect("required option missing"),
+        matches
+            .value_of("system")
+            .ok_or_else(|| format_err!("The system name (-s) is missing"))?,
     );
 
     info!("Using system description at: {}", cfg_system.display());
@@ -386,8 +888,13 @@ pub fn main() -> Result<(), Error> {
 
     debug!("Configured system is: {:#x?}", configured_system);
 
-    if let Some(_) = matches.subcommand_matches("verify") {
-        epoxy_verify(&configured_system)
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        epoxy_verify(
+            &configured_system,
+            verify_matches.value_of("user-binaries").map(Path::new),
+        )
+    } else if matches.subcommand_matches("analyze-schedule").is_some() {
+        epoxy_analyze_schedule(&configured_system)
     } else if let Some(cfg_proc_matches) = matches.subcommand_matches("configure-process") {
         epoxy_configure_process(
             &configured_system,
@@ -397,13 +904,19 @@ pub fn main() -> Result<(), Error> {
             cfg_proc_matches
                 .value_of("language")
                 .expect("option with default value missing"),
+            cfg_proc_matches.value_of("output").map(Path::new),
         )
     } else if let Some(cfg_kern_matches) = matches.subcommand_matches("configure-kernel") {
         epoxy_configure_kernel(
             &configured_system,
-            cfg_kern_matches
-                .value_of("type")
-                .expect("required option missing"),
+            &cfg_kern_matches
+                .values_of("type")
+                .expect("required option missing")
+                .collect::<Vec<&str>>(),
+            &cfg_kern_matches
+                .values_of("output")
+                .map(|v| v.map(Path::new).collect::<Vec<&Path>>())
+                .unwrap_or_default(),
         )
     } else if let Some(boot_image_matches) = matches.subcommand_matches("boot-image") {
         epoxy_boot_image(
@@ -413,8 +926,92 @@ pub fn main() -> Result<(), Error> {
                     .value_of("user-binaries")
                     .expect("required option missing"),
             ),
+            boot_image_matches.value_of("dtb").map(Path::new),
+            &boot_image::Options {
+                generate_dtb: boot_image_matches.is_present("generate-dtb"),
+                debug_info: boot_image_matches.is_present("debug-info"),
+                kernel_at_paddr: boot_image_matches.is_present("kernel-at-paddr"),
+                format: boot_image_matches
+                    .value_of("format")
+                    .expect("option with default value missing")
+                    .parse()?,
+            },
+            boot_image_matches.value_of("output").map(Path::new),
         )
     } else {
         Err(format_err!("Unknown subcommand"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sched_params() {
+        let default = sched_params(&cfgtypes::Scheduling::default(), 1_000_000).unwrap();
+
+        assert_eq!(default.priority, 0);
+        assert_eq!(default.time_slice, 1_000_000 / DEFAULT_SCHEDULE_HZ);
+
+        let sched = |priority, time_slice, budget| cfgtypes::Scheduling {
+            priority,
+            time_slice,
+            budget,
+        };
+        let budget = |budget, period| Some(cfgtypes::Budget { budget, period });
+
+        assert!(sched_params(&sched(256, None, None), 1_000_000).is_err());
+        assert!(sched_params(&sched(0, Some(0), None), 1_000_000).is_err());
+        assert!(sched_params(&sched(0, Some(10), None), 1_000_000).is_err());
+        assert!(sched_params(&sched(0, Some(100), budget(100, 1000)), 1_000_000).is_ok());
+        assert!(sched_params(&sched(0, Some(200), budget(100, 1000)), 1_000_000).is_err());
+        assert!(sched_params(&sched(0, Some(100), budget(2000, 1000)), 1_000_000).is_err());
+    }
+
+    #[test]
+    fn test_stack_size() {
+        let app = |stack_kb, tls_kb| cfgtypes::Application {
+            name: "app".to_string(),
+            heap_kb: 0,
+            stack_kb,
+            tls_kb,
+            needs: vec![],
+        };
+
+        assert_eq!(stack_size(&app(16, 4)).unwrap(), 0x4000);
+        assert!(stack_size(&app(0, 4)).is_err());
+        assert!(stack_size(&app(6, 4)).is_err());
+        assert!(stack_size(&app(u64::MAX, 4)).is_err());
+
+        assert_eq!(tls_size(&app(16, 8)).unwrap(), 0x2000);
+        assert!(tls_size(&app(16, 0)).is_err());
+        assert!(tls_size(&app(16, 2)).is_err());
+    }
+
+    #[test]
+    fn test_check_harts() {
+        let on_hart = |hart| runtypes::Process {
+            name: format!("p{}", hart),
+            binary: String::new(),
+            resources: runtypes::ResourceMap::new(),
+            anon_mem: vec![],
+            tls: None,
+            config: None,
+            stack_ptr: 0,
+            initial_regs: [0; ARG_REGISTERS],
+            sched: runtypes::SchedParams::default(),
+            hart,
+            load_base: None,
+        };
+
+        assert!(check_harts(1, &[], &[on_hart(0)]).is_ok());
+        assert!(check_harts(2, &[1], &[on_hart(0)]).is_ok());
+        assert!(check_harts(0, &[], &[]).is_err());
+        assert!(check_harts(1, &[], &[on_hart(1)]).is_err());
+        assert!(check_harts(2, &[], &[on_hart(0)]).is_err());
+        assert!(check_harts(1, &[0], &[on_hart(0)]).is_err());
+        assert!(check_harts(1, &[3], &[on_hart(0)]).is_err());
+        assert!(check_harts(2, &[0], &[on_hart(1)]).is_err());
+    }
+}
@@ -0,0 +1,778 @@
+//! A minimal reader and writer for flattened device trees (FDT/DTB).
+//!
+//! We only need to look at a handful of nodes to learn about memory and devices of a machine, so
+//! this parses the whole structure block into a simple tree without interpreting any properties.
+//! The same tree can be serialized again to hand a device tree to firmware. See the Devicetree
+//! Specification [1] for the format.
+//!
+//! Addresses in `reg` properties are translated to physical addresses using the `ranges` of all
+//! parent buses. Buses without `ranges` are treated as if they map one-to-one.
+//!
+//! [1] https://www.devicetree.org/specifications/
+
+use anyhow::Error;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::fs;
+use std::path::Path;
+
+use crate::interval::Interval;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// The version of the format we understand.
+const FDT_VERSION: u32 = 17;
+
+/// The oldest version that can read what we write.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// The size of the FDT header in bytes.
+const HEADER_SIZE: usize = 40;
+
+/// The deepest node nesting we accept. Real device trees are only a few levels deep, and the
+/// parser recurses for each level.
+const MAX_NODE_DEPTH: usize = 64;
+
+/// The default number of cells of addresses and sizes, if a node does not specify them.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// The node name including the unit address, e.g. `memory@80000000`.
+    pub name: String,
+    pub properties: Vec<(String, Vec<u8>)>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(name: &str) -> Node {
+        Node {
+            name: name.to_string(),
+            properties: vec![],
+            children: vec![],
+        }
+    }
+
+    pub fn with_property(mut self, name: &str, value: Vec<u8>) -> Node {
+        self.properties.push((name.to_string(), value));
+        self
+    }
+
+    pub fn with_child(mut self, child: Node) -> Node {
+        self.children.push(child);
+        self
+    }
+
+    /// Returns the node name without its unit address.
+    pub fn base_name(&self) -> &str {
+        self.name.split('@').next().unwrap_or("")
+    }
+
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Returns a property that is a list of zero-terminated strings, such as `compatible`.
+    pub fn strings(&self, name: &str) -> Vec<&str> {
+        self.property(name)
+            .map(|v| {
+                v.split(|&c| c == 0)
+                    .filter(|s| !s.is_empty())
+                    .filter_map(|s| std::str::from_utf8(s).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        self.strings(name).first().copied()
+    }
+
+    pub fn u32(&self, name: &str) -> Option<u32> {
+        self.property(name)
+            .filter(|v| v.len() == 4)
+            .map(BigEndian::read_u32)
+    }
+
+    /// Returns a property that is either a single or a double cell.
+    pub fn u64(&self, name: &str) -> Option<u64> {
+        self.property(name).and_then(|v| match v.len() {
+            4 => Some(BigEndian::read_u32(v).into()),
+            8 => Some(BigEndian::read_u64(v)),
+            _ => None,
+        })
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.strings("compatible").contains(&compatible)
+    }
+
+    /// Returns whether the node is usable. Nodes without a `status` property are.
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.string("status"), None | Some("okay") | Some("ok"))
+    }
+
+    /// Find a direct child by name. The name may be given with or without unit address.
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children
+            .iter()
+            .find(|c| c.name == name || c.base_name() == name)
+    }
+
+    fn address_cells(&self) -> u32 {
+        self.u32("#address-cells").unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    fn size_cells(&self) -> u32 {
+        self.u32("#size-cells").unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+}
+
+/// Encode a property value that is a list of cells.
+pub fn cells(values: &[u32]) -> Vec<u8> {
+    let mut out = vec![];
+
+    for &v in values {
+        out.write_u32::<BigEndian>(v).unwrap();
+    }
+
+    out
+}
+
+/// Encode a property value that is a list of strings.
+pub fn strings(values: &[&str]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|s| s.bytes().chain(std::iter::once(0)))
+        .collect()
+}
+
+/// Encode a `reg` property with two address and two size cells.
+pub fn reg64(regions: &[Interval]) -> Vec<u8> {
+    let mut out = vec![];
+
+    for r in regions {
+        out.write_u64::<BigEndian>(r.from).unwrap();
+        out.write_u64::<BigEndian>(r.size()).unwrap();
+    }
+
+    out
+}
+
+/// A window of a bus address space into its parent's address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Range {
+    child: Interval,
+    phys: u64,
+}
+
+/// A node together with the information from its parents that is needed to interpret it.
+#[derive(Debug, Clone)]
+pub struct NodeRef<'a> {
+    pub node: &'a Node,
+    address_cells: u32,
+    size_cells: u32,
+
+    /// The translation of addresses in `reg` to physical addresses. `None` means one-to-one.
+    ranges: Option<Vec<Range>>,
+}
+
+impl<'a> NodeRef<'a> {
+    fn translate(&self, region: Interval) -> Result<Interval, Error> {
+        match &self.ranges {
+            None => Ok(region),
+            Some(ranges) => {
+                let range = ranges
+                    .iter()
+                    .find(|r| r.child.intersection(region) == region)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "Region {:#x}-{:#x} of {} is not reachable from the root",
+                            region.from,
+                            region.to,
+                            self.node.name
+                        )
+                    })?;
+
+                range
+                    .phys
+                    .checked_add(region.from - range.child.from)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "Region {:#x}-{:#x} of {} overflows when translated",
+                            region.from,
+                            region.to,
+                            self.node.name
+                        )
+                    })
+                    .and_then(|from| Interval::try_new_with_size(from, region.size()))
+            }
+        }
+    }
+
+    /// Returns the memory regions in the `reg` property of the node as physical addresses.
+    pub fn reg(&self) -> Result<Vec<Interval>, Error> {
+        parse_regions(self.node, "reg", &[self.address_cells, self.size_cells])?
+            .into_iter()
+            .map(|r| self.translate(Interval::try_new_with_size(r[0], r[1])?))
+            .collect()
+    }
+
+    /// Returns the address translation for the children of this node.
+    fn child_ranges(&self) -> Result<Option<Vec<Range>>, Error> {
+        if self.node.property("ranges").is_none() {
+            return Ok(self.ranges.clone());
+        }
+
+        parse_regions(
+            self.node,
+            "ranges",
+            &[
+                self.node.address_cells(),
+                self.address_cells,
+                self.node.size_cells(),
+            ],
+        )?
+        .into_iter()
+        .map(|r| {
+            Ok(Range {
+                child: Interval::try_new_with_size(r[0], r[2])?,
+                phys: self
+                    .translate(Interval::try_new_with_size(r[1], r[2])?)?
+                    .from,
+            })
+        })
+        .collect::<Result<Vec<Range>, Error>>()
+        // An empty ranges property means one-to-one translation.
+        .map(|ranges| {
+            if ranges.is_empty() {
+                self.ranges.clone()
+            } else {
+                Some(ranges)
+            }
+        })
+    }
+}
+
+/// Parse a property that consists of tuples of numbers with the given number of cells each.
+fn parse_regions(node: &Node, name: &str, cells: &[u32]) -> Result<Vec<Vec<u64>>, Error> {
+    if let Some(c) = cells.iter().find(|&&c| c > 2) {
+        return Err(format_err!(
+            "Failed to parse {} of {}: Numbers with {} cells are not supported",
+            name,
+            node.name,
+            c
+        ));
+    }
+
+    let value = node.property(name).unwrap_or(&[]);
+    let entry_len = 4 * cells.iter().map(|&c| c as usize).sum::<usize>();
+
+    if entry_len == 0 || value.len() % entry_len != 0 {
+        return Err(format_err!(
+            "Node {} has a malformed {} property",
+            node.name,
+            name
+        ));
+    }
+
+    value
+        .chunks(entry_len)
+        .map(|mut entry| {
+            cells
+                .iter()
+                .map(|&c| {
+                    let (number, rest) = entry.split_at(4 * c as usize);
+
+                    entry = rest;
+                    read_cells(number)
+                })
+                .collect()
+        })
+        .collect::<Result<Vec<Vec<u64>>, Error>>()
+        .map_err(|e| e.context(format!("Failed to parse {} of {}", name, node.name)))
+}
+
+/// Read a number that is encoded as one or two cells.
+fn read_cells(cells: &[u8]) -> Result<u64, Error> {
+    match cells.len() {
+        0 => Ok(0),
+        4 => Ok(BigEndian::read_u32(cells).into()),
+        8 => Ok(BigEndian::read_u64(cells)),
+        _ => Err(format_err!(
+            "Numbers with {} cells are not supported",
+            cells.len() / 4
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fdt {
+    /// The physical ID of the boot CPU.
+    pub boot_cpuid: u32,
+
+    /// Memory that must not be used, from the memory reservation block.
+    pub reserved: Vec<Interval>,
+
+    pub root: Node,
+}
+
+/// A cursor into the structure block.
+struct Parser<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn token(&mut self) -> Result<u32, Error> {
+        let bytes = self
+            .structs
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| format_err!("Structure block ends unexpectedly"))?;
+
+        self.pos += 4;
+        Ok(BigEndian::read_u32(bytes))
+    }
+
+    /// Read a zero-terminated string and skip the padding after it.
+    fn name(&mut self) -> Result<String, Error> {
+        let rest = self.structs.get(self.pos..).unwrap_or(&[]);
+        let len = rest
+            .iter()
+            .position(|&c| c == 0)
+            .ok_or_else(|| format_err!("Unterminated node name"))?;
+        let name = String::from_utf8(rest[..len].to_vec())?;
+
+        self.pos += (len + 1 + 3) & !3;
+        Ok(name)
+    }
+
+    fn property(&mut self) -> Result<(String, Vec<u8>), Error> {
+        let len = self.token()? as usize;
+        let name_offset = self.token()? as usize;
+
+        let value = self
+            .structs
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| format_err!("Property value is out of bounds"))?
+            .to_vec();
+        self.pos += (len + 3) & !3;
+
+        let name = self
+            .strings
+            .get(name_offset..)
+            .and_then(|s| s.split(|&c| c == 0).next())
+            .ok_or_else(|| format_err!("Property name is out of bounds"))?;
+
+        Ok((String::from_utf8(name.to_vec())?, value))
+    }
+
+    /// Parse a node at the given depth, starting with 1 for the root node. The FDT_BEGIN_NODE token
+    /// has already been consumed.
+    fn node(&mut self, depth: usize) -> Result<Node, Error> {
+        if depth > MAX_NODE_DEPTH {
+            return Err(format_err!(
+                "Device tree nodes are nested deeper than {} levels",
+                MAX_NODE_DEPTH
+            ));
+        }
+
+        let mut node = Node {
+            name: self.name()?,
+            properties: vec![],
+            children: vec![],
+        };
+
+        loop {
+            match self.token()? {
+                FDT_PROP if node.children.is_empty() => node.properties.push(self.property()?),
+                FDT_BEGIN_NODE => node.children.push(self.node(depth + 1)?),
+                FDT_END_NODE => return Ok(node),
+                FDT_NOP => {}
+                token => {
+                    return Err(format_err!(
+                        "Unexpected token {:#x} in node '{}'",
+                        token,
+                        node.name
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Returns the sub-slice of the blob that starts at `offset` and has `size` bytes.
+fn block(data: &[u8], offset: u32, size: u32) -> Result<&[u8], Error> {
+    let start = offset as usize;
+    let end = start
+        .checked_add(size as usize)
+        .ok_or_else(|| format_err!("Block size overflows"))?;
+
+    data.get(start..end)
+        .ok_or_else(|| format_err!("Block at {:#x} is out of bounds", offset))
+}
+
+impl Fdt {
+    pub fn parse(data: &[u8]) -> Result<Fdt, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(format_err!("Device tree is too short"));
+        }
+
+        let header = |i: usize| BigEndian::read_u32(&data[4 * i..]);
+
+        if header(0) != FDT_MAGIC {
+            return Err(format_err!("Not a flattened device tree"));
+        }
+
+        // Older versions lack the size of the structure block.
+        if header(5) < FDT_VERSION || header(6) > FDT_VERSION {
+            return Err(format_err!(
+                "Device tree version {} is not supported",
+                header(5)
+            ));
+        }
+
+        let data = data
+            .get(..header(1) as usize)
+            .ok_or_else(|| format_err!("Device tree is truncated"))?;
+
+        let mut parser = Parser {
+            structs: block(data, header(2), header(9))?,
+            strings: block(data, header(3), header(8))?,
+            pos: 0,
+        };
+
+        let root = loop {
+            match parser.token()? {
+                FDT_NOP => {}
+                FDT_BEGIN_NODE => break parser.node(1)?,
+                token => return Err(format_err!("Unexpected token {:#x} before root", token)),
+            }
+        };
+
+        loop {
+            match parser.token()? {
+                FDT_NOP => {}
+                FDT_END => break,
+                token => return Err(format_err!("Unexpected token {:#x} after root", token)),
+            }
+        }
+
+        let reserved = data
+            .get(header(4) as usize..)
+            .unwrap_or(&[])
+            .chunks_exact(16)
+            .map(|entry| {
+                (
+                    BigEndian::read_u64(&entry[0..8]),
+                    BigEndian::read_u64(&entry[8..16]),
+                )
+            })
+            .take_while(|&(address, size)| address != 0 || size != 0)
+            .map(|(address, size)| Interval::try_new_with_size(address, size))
+            .collect::<Result<Vec<Interval>, Error>>()
+            .map_err(|e| e.context("Malformed memory reservation block"))?;
+
+        Ok(Fdt {
+            boot_cpuid: header(7),
+            reserved,
+            root,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Fdt, Error> {
+        Fdt::parse(&fs::read(path)?)
+            .map_err(|e| e.context(format!("Failed to parse device tree {}", path.display())))
+    }
+
+    /// Returns all nodes in depth-first order.
+    pub fn nodes(&self) -> Result<Vec<NodeRef<'_>>, Error> {
+        fn collect<'a>(parent: &NodeRef<'a>, out: &mut Vec<NodeRef<'a>>) -> Result<(), Error> {
+            let ranges = parent.child_ranges()?;
+
+            for child in &parent.node.children {
+                let child = NodeRef {
+                    node: child,
+                    address_cells: parent.node.address_cells(),
+                    size_cells: parent.node.size_cells(),
+                    ranges: ranges.clone(),
+                };
+
+                out.push(child.clone());
+                collect(&child, out)?;
+            }
+
+            Ok(())
+        }
+
+        let root = NodeRef {
+            node: &self.root,
+            address_cells: DEFAULT_ADDRESS_CELLS,
+            size_cells: DEFAULT_SIZE_CELLS,
+            ranges: None,
+        };
+        let mut out = vec![root.clone()];
+
+        collect(&root, &mut out)?;
+        Ok(out)
+    }
+
+    /// Serialize the device tree into a blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn pad(out: &mut Vec<u8>) {
+            out.resize((out.len() + 3) & !3, 0);
+        }
+
+        fn write_node(node: &Node, structs: &mut Vec<u8>, strings: &mut Vec<u8>) {
+            structs.write_u32::<BigEndian>(FDT_BEGIN_NODE).unwrap();
+            structs.extend(node.name.bytes().chain(std::iter::once(0)));
+            pad(structs);
+
+            for (name, value) in &node.properties {
+                let name_offset = find_or_add_string(strings, name);
+
+                structs.write_u32::<BigEndian>(FDT_PROP).unwrap();
+                structs.write_u32::<BigEndian>(value.len() as u32).unwrap();
+                structs.write_u32::<BigEndian>(name_offset).unwrap();
+                structs.extend(value);
+                pad(structs);
+            }
+
+            for child in &node.children {
+                write_node(child, structs, strings);
+            }
+
+            structs.write_u32::<BigEndian>(FDT_END_NODE).unwrap();
+        }
+
+        /// Property names are stored only once in the strings block.
+        fn find_or_add_string(strings: &mut Vec<u8>, name: &str) -> u32 {
+            let mut needle = name.as_bytes().to_vec();
+            needle.push(0);
+
+            let offset = strings
+                .windows(needle.len())
+                .enumerate()
+                .find(|&(i, w)| w == needle.as_slice() && (i == 0 || strings[i - 1] == 0))
+                .map(|(i, _)| i)
+                .unwrap_or_else(|| {
+                    strings.extend(&needle);
+                    strings.len() - needle.len()
+                });
+
+            offset as u32
+        }
+
+        let mut reserved = vec![];
+        for r in self
+            .reserved
+            .iter()
+            .chain(std::iter::once(&Interval::default()))
+        {
+            reserved.write_u64::<BigEndian>(r.from).unwrap();
+            reserved.write_u64::<BigEndian>(r.size()).unwrap();
+        }
+
+        let mut structs = vec![];
+        let mut strings = vec![];
+
+        write_node(&self.root, &mut structs, &mut strings);
+        structs.write_u32::<BigEndian>(FDT_END).unwrap();
+
+        let off_reserved = HEADER_SIZE;
+        let off_structs = off_reserved + reserved.len();
+        let off_strings = off_structs + structs.len();
+        let total_size = off_strings + strings.len();
+
+        let mut out = vec![];
+
+        for field in &[
+            FDT_MAGIC,
+            total_size as u32,
+            off_structs as u32,
+            off_strings as u32,
+            off_reserved as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            self.boot_cpuid,
+            strings.len() as u32,
+            structs.len() as u32,
+        ] {
+            out.write_u32::<BigEndian>(*field).unwrap();
+        }
+
+        out.extend(reserved);
+        out.extend(structs);
+        out.extend(strings);
+        out
+    }
+
+    /// Look up a node by its absolute path, e.g. `/cpus`.
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(&self.root, |node, component| node.child(component))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qemu() -> Fdt {
+        Fdt::parse(include_bytes!("../testdata/qemu-virt.dtb")).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let fdt = qemu();
+        let plic = fdt.find("/soc/plic@c000000").unwrap();
+
+        assert_eq!(fdt.boot_cpuid, 0);
+        assert!(fdt.reserved.is_empty());
+        assert!(plic.is_compatible("riscv,plic0"));
+        assert_eq!(plic.u32("riscv,ndev"), Some(0x35));
+        assert_eq!(fdt.find("/soc/plic").map(|n| &n.name), Some(&plic.name));
+        assert_eq!(
+            fdt.find("/cpus/cpu@0").and_then(|n| n.string("riscv,isa")),
+            Some("rv64imafdcsu")
+        );
+        assert!(fdt.find("/soc/nothing").is_none());
+    }
+
+    #[test]
+    fn test_reg_translation() {
+        let fdt = Fdt::parse(include_bytes!("../testdata/ulx3s-saxonsoc.dtb")).unwrap();
+        let nodes = fdt.nodes().unwrap();
+        let reg = |name: &str| {
+            nodes
+                .iter()
+                .find(|n| n.node.name == name)
+                .unwrap()
+                .reg()
+                .unwrap()
+        };
+
+        assert_eq!(
+            fdt.reserved,
+            vec![Interval::new_with_size(0x80000000, 0x800000)]
+        );
+        assert_eq!(
+            reg("memory@80000000"),
+            vec![Interval::new_with_size(0x80000000, 0x2000000)]
+        );
+
+        // Devices on the APB bus are relocated by its ranges property.
+        assert_eq!(
+            reg("plic@c00000"),
+            vec![Interval::new_with_size(0x10c00000, 0x400000)]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let fdt = qemu();
+
+        assert_eq!(Fdt::parse(&fdt.to_bytes()).unwrap(), fdt);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut dtb = include_bytes!("../testdata/qemu-virt.dtb").to_vec();
+
+        assert!(Fdt::parse(&dtb[..HEADER_SIZE - 1]).is_err());
+
+        // Truncate the structure block.
+        dtb.truncate(dtb.len() / 2);
+        assert!(Fdt::parse(&dtb).is_err());
+
+        dtb[0] = 0;
+        assert!(Fdt::parse(&dtb).is_err());
+    }
+
+    #[test]
+    fn test_overflowing_regions() {
+        let fdt = |device: Node| Fdt {
+            boot_cpuid: 0,
+            reserved: vec![],
+            root: Node::new("")
+                .with_property("#address-cells", cells(&[2]))
+                .with_property("#size-cells", cells(&[1]))
+                .with_child(device),
+        };
+        let reg = |fdt: &Fdt| {
+            fdt.nodes()?
+                .iter()
+                .map(|n| n.reg())
+                .collect::<Result<Vec<Vec<Interval>>, Error>>()
+        };
+
+        assert!(reg(&fdt(
+            Node::new("dev").with_property("reg", cells(&[0, 0x1000, 0x100]))
+        ))
+        .is_ok());
+        assert!(reg(&fdt(
+            Node::new("dev").with_property("reg", cells(&[u32::MAX, u32::MAX, 0x100]))
+        ))
+        .is_err());
+
+        // Bus windows that overflow the parent address space.
+        assert!(reg(&fdt(Node::new("bus")
+            .with_property("#address-cells", cells(&[1]))
+            .with_property(
+                "ranges",
+                cells(&[0, u32::MAX, 0xffff_f000, 0x2000])
+            )))
+        .is_err());
+
+        // Huge cell counts must not overflow the entry size.
+        assert!(reg(&fdt(Node::new("bus")
+            .with_property("#address-cells", cells(&[u32::MAX / 2]))
+            .with_child(
+                Node::new("dev").with_property("reg", cells(&[0, 0x100]))
+            )))
+        .is_err());
+        assert!(reg(&fdt(Node::new("bus")
+            .with_property("#address-cells", cells(&[3]))
+            .with_child(
+                Node::new("dev").with_property("reg", cells(&[0, 0, 0, 0x100]))
+            )))
+        .is_err());
+    }
+
+    #[test]
+    fn test_overflowing_reservation() {
+        let mut dtb = Fdt {
+            boot_cpuid: 0,
+            reserved: vec![Interval::new_with_size(0x1000, 0x1000)],
+            root: Node::new(""),
+        }
+        .to_bytes();
+        let off_reserved = BigEndian::read_u32(&dtb[16..20]) as usize;
+
+        BigEndian::write_u64(&mut dtb[off_reserved..off_reserved + 8], u64::MAX);
+        assert!(Fdt::parse(&dtb).is_err());
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let nested = |depth| Fdt {
+            boot_cpuid: 0,
+            reserved: vec![],
+            root: (1..depth).fold(Node::new(""), |child, _| Node::new("n").with_child(child)),
+        };
+
+        assert!(Fdt::parse(&nested(MAX_NODE_DEPTH).to_bytes()).is_ok());
+        assert!(Fdt::parse(&nested(MAX_NODE_DEPTH + 1).to_bytes()).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Writers for the text-based Intel HEX and Motorola S-record formats.
+//!
+//! Both formats describe memory as a sequence of records that each carry an address, a few data
+//! bytes and a checksum. Flashing tools and serial boot loaders often prefer them to ELF files. We
+//! always use the 32-bit variants of both formats:
+//!
+//! - Intel HEX: data records (00) with extended linear address records (04) for the upper address
+//!   bits, a start linear address record (05) for the entry point and an end-of-file record (01).
+//! - S-records: a header (S0), data records with 32-bit addresses (S3) and a termination record
+//!   with the entry point (S7).
+
+use anyhow::Error;
+use itertools::Itertools;
+use std::convert::{TryFrom, TryInto};
+use std::io::Write;
+
+use crate::phys_mem::PhysMemory;
+
+/// The number of data bytes in each data record.
+const RECORD_DATA_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    IntelHex,
+    Srec,
+}
+
+/// Split all populated memory into pieces of at most `RECORD_DATA_LEN` bytes that don't cross a
+/// 64 KiB boundary, because Intel HEX records only have 16 bits of address.
+fn records(pmem: &PhysMemory) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let mut out = vec![];
+
+    for chunk in pmem.chunks() {
+        let mut paddr = chunk.paddr;
+        let mut data = chunk.data.as_slice();
+
+        while !data.is_empty() {
+            let to_boundary: usize = (0x10000 - (paddr & 0xffff)).try_into()?;
+            let (record, rest) = data.split_at(data.len().min(RECORD_DATA_LEN).min(to_boundary));
+
+            out.push((
+                paddr
+                    .try_into()
+                    .map_err(|_| format_err!("Address {:#x} does not fit into 32 bits", paddr))?,
+                record.to_vec(),
+            ));
+
+            paddr += u64::try_from(record.len())?;
+            data = rest;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Format a record as hex digits with a trailing checksum.
+fn hex_record(prefix: &str, bytes: &[u8], checksum: u8) -> String {
+    format!(
+        "{}{}{:02X}\n",
+        prefix,
+        bytes.iter().map(|b| format!("{:02X}", b)).join(""),
+        checksum
+    )
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc: u8, &b| acc.wrapping_add(b))
+}
+
+/// Returns an Intel HEX record with the given type, 16-bit address and data.
+fn ihex_record(rtype: u8, addr: u16, data: &[u8]) -> String {
+    let bytes = [&[data.len() as u8][..], &addr.to_be_bytes(), &[rtype], data].concat();
+
+    hex_record(":", &bytes, sum(&bytes).wrapping_neg())
+}
+
+/// Returns an S-record of the given type with the address and data.
+fn srec_record(rtype: u8, addr: &[u8], data: &[u8]) -> String {
+    // The count includes the address, the data and the checksum.
+    let bytes = [&[(addr.len() + data.len() + 1) as u8][..], addr, data].concat();
+
+    hex_record(&format!("S{}", rtype), &bytes, !sum(&bytes))
+}
+
+fn write_ihex<T: Write>(buf: &mut T, entry: u32, pmem: &PhysMemory) -> Result<(), Error> {
+    let mut upper = None;
+
+    for (paddr, data) in records(pmem)? {
+        let paddr_upper = (paddr >> 16) as u16;
+
+        if upper != Some(paddr_upper) {
+            buf.write_all(ihex_record(0x04, 0, &paddr_upper.to_be_bytes()).as_bytes())?;
+            upper = Some(paddr_upper);
+        }
+
+        buf.write_all(ihex_record(0x00, paddr as u16, &data).as_bytes())?;
+    }
+
+    buf.write_all(ihex_record(0x05, 0, &entry.to_be_bytes()).as_bytes())?;
+    buf.write_all(ihex_record(0x01, 0, &[]).as_bytes())?;
+
+    Ok(())
+}
+
+fn write_srec<T: Write>(buf: &mut T, entry: u32, pmem: &PhysMemory) -> Result<(), Error> {
+    buf.write_all(srec_record(0, &[0, 0], b"harden").as_bytes())?;
+
+    for (paddr, data) in records(pmem)? {
+        buf.write_all(srec_record(3, &paddr.to_be_bytes(), &data).as_bytes())?;
+    }
+
+    buf.write_all(srec_record(7, &entry.to_be_bytes(), &[]).as_bytes())?;
+
+    Ok(())
+}
+
+pub fn write<T: Write>(
+    buf: &mut T,
+    format: Format,
+    entry: u64,
+    pmem: &PhysMemory,
+) -> Result<(), Error> {
+    let entry: u32 = entry
+        .try_into()
+        .map_err(|_| format_err!("Entry point {:#x} does not fit into 32 bits", entry))?;
+
+    match format {
+        Format::IntelHex => write_ihex(buf, entry, pmem),
+        Format::Srec => write_srec(buf, entry, pmem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_string(format: Format, pmem: &PhysMemory) -> String {
+        let mut out = vec![];
+
+        write(&mut out, format, 0x8000_0000, pmem).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_records() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+
+        pmem.write(0x1fff8, &[0xab; 20]);
+
+        // Records are split at the 64 KiB boundary and after RECORD_DATA_LEN bytes.
+        assert_eq!(
+            records(&pmem)
+                .unwrap()
+                .iter()
+                .map(|(a, d)| (*a, d.len()))
+                .collect::<Vec<_>>(),
+            vec![(0x1fff8, 8), (0x20000, 12)]
+        );
+
+        pmem.write(0x1_0000_0000, &[0]);
+        assert!(records(&pmem).is_err());
+    }
+
+    #[test]
+    fn test_ihex() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+
+        pmem.write(0x8000_0100, &[0x01, 0x02, 0x03]);
+
+        assert_eq!(
+            to_string(Format::IntelHex, &pmem),
+            ":0200000480007A\n:03010000010203F6\n:040000058000000077\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn test_srec() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+
+        pmem.write(0x8000_0100, &[0x01, 0x02, 0x03]);
+
+        assert_eq!(
+            to_string(Format::Srec, &pmem),
+            "S009000068617264656E84\nS3088000010001020370\nS705800000007A\n"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use anyhow::Error;
+
 /// A half-open interval.
 #[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Interval {
@@ -16,6 +18,16 @@ impl Interval {
         }
     }
 
+    /// Like `new_with_size`, but fails instead of overflowing. Use this for untrusted values.
+    pub fn try_new_with_size(start: u64, size: u64) -> Result<Interval, Error> {
+        Ok(Interval {
+            from: start,
+            to: start.checked_add(size).ok_or_else(|| {
+                format_err!("Region at {:#x} with size {:#x} overflows", start, size)
+            })?,
+        })
+    }
+
     /// Checks whether an interval is empty.
     pub fn empty(&self) -> bool {
         self.from >= self.to
@@ -101,6 +113,9 @@ mod tests {
 
         assert!(Interval::new_with_size(23, 0).empty());
 
+        assert_eq!(Interval::try_new_with_size(0, 5).unwrap(), i1);
+        assert!(Interval::try_new_with_size(u64::MAX, 1).is_err());
+
         assert!(i1.adjacent(i3));
         assert!(!i1.adjacent(i4));
 
@@ -1,5 +1,6 @@
 use anyhow::Error;
 use itertools::Itertools;
+use std::convert::{TryFrom, TryInto};
 
 use crate::runtypes;
 
@@ -11,6 +12,7 @@ enum Expression {
     LiteralString(String),
     Identifier(String),
     AddressOf(Box<Expression>),
+    InitializerList(Vec<Expression>),
 }
 
 impl std::fmt::Display for Expression {
@@ -19,6 +21,9 @@ impl std::fmt::Display for Expression {
             Expression::LiteralUnsigned(i) => write!(f, "{:#x}", i),
             Expression::Identifier(i) => write!(f, "{}", i),
             Expression::AddressOf(e) => write!(f, "&({})", e),
+            Expression::InitializerList(l) => {
+                write!(f, "{{{}}}", l.iter().map(|e| e.to_string()).join(", "))
+            }
 
             // TODO Quote string!
             Expression::LiteralString(s) => write!(f, "\"{}\"", s),
@@ -106,6 +111,14 @@ mod tests {
             Expression::AddressOf(Box::new(Expression::Identifier("foo".to_string()))).to_string(),
             "&(foo)"
         );
+        assert_eq!(
+            Expression::InitializerList(vec![
+                Expression::LiteralUnsigned(1),
+                Expression::Identifier("bar".to_string())
+            ])
+            .to_string(),
+            "{0x1, bar}"
+        );
     }
 
     #[test]
@@ -164,7 +177,7 @@ impl Iterator for IdentifierIterator {
 
     fn next(&mut self) -> std::option::Option<<Self as std::iter::Iterator>::Item> {
         let new_identifier = format!("id_{}", self.id);
-        self.id = self.id + 1;
+        self.id += 1;
         Some(new_identifier)
     }
 }
@@ -173,6 +186,18 @@ fn pointer_to(s: &str) -> Expression {
     Expression::AddressOf(Box::new(Expression::Identifier(s.to_string())))
 }
 
+/// Returns the initializer for the `sched_params` of a thread.
+fn sched_params(sched: &runtypes::SchedParams) -> Expression {
+    let (budget, period) = sched.budget.map_or((0, 0), |b| (b.budget, b.period));
+
+    Expression::InitializerList(vec![
+        Expression::LiteralUnsigned(sched.priority.into()),
+        Expression::LiteralUnsigned(sched.time_slice),
+        Expression::LiteralUnsigned(budget),
+        Expression::LiteralUnsigned(period),
+    ])
+}
+
 /// Returns the name of the thread that is created in addition to all statements that need to go
 /// into the state file to create the necessary kernel options.
 fn process_kobjects(
@@ -215,17 +240,59 @@ fn process_kobjects(
             Statement::VariableDefinition {
                 r#type: "thread".to_string(),
                 name: thread_name,
-                init_args: vec![
+                init_args: [
                     pointer_to(&proc_name),
                     Expression::LiteralUnsigned(process.stack_ptr),
-                    Expression::LiteralUnsigned(process.heap_start),
-                    Expression::LiteralUnsigned(process.heap_end),
-                ],
+                    Expression::LiteralUnsigned(process.tls.as_ref().map_or(0, |t| t.virt_start)),
+                ]
+                .iter()
+                .cloned()
+                .chain(
+                    process
+                        .initial_regs
+                        .iter()
+                        .map(|&r| Expression::LiteralUnsigned(r)),
+                )
+                .chain(std::iter::once(sched_params(&process.sched)))
+                .collect(),
             },
         ],
     ))
 }
 
+/// Returns the statements that define the thread list of a hart and the `hart_state` initializer
+/// that refers to it.
+fn hart_state(
+    hart: u64,
+    threads: &[&str],
+    stack: &runtypes::VirtualMemoryRegion,
+) -> (Vec<Statement>, Expression) {
+    let threads_name = format!("hart_{}_threads", hart);
+
+    // Idle harts have no thread list, because C++ does not allow empty arrays.
+    let (statements, threads_expr) = if threads.is_empty() {
+        (vec![], Expression::Identifier("nullptr".to_string()))
+    } else {
+        (
+            vec![Statement::ArrayDefinition {
+                r#type: "thread * const".to_string(),
+                name: threads_name.clone(),
+                init_args: threads.iter().map(|t| pointer_to(t)).collect(),
+            }],
+            Expression::Identifier(threads_name),
+        )
+    };
+
+    (
+        statements,
+        Expression::InitializerList(vec![
+            threads_expr,
+            Expression::LiteralUnsigned(threads.len().try_into().unwrap()),
+            Expression::LiteralUnsigned(stack.virt_start + stack.size()),
+        ]),
+    )
+}
+
 /// Generate the C++ code for the kernel configuration.
 ///
 /// The output will look like this:
@@ -234,17 +301,14 @@ fn process_kobjects(
 /// #include "state.hpp"
 /// #include "kobject_all.hpp"
 /// namespace {
-/// extern exit_kobject kobject_0;
-/// extern klog_kobject kobject_1;
-/// extern process kobject_2;
-/// extern thread kobject_3;
-/// kobject * const p0_capability_set[2] {&(kobject_0),&(kobject_1)};
-/// exit_kobject kobject_0 {};
-/// klog_kobject kobject_1 {"hello"};
-/// process kobject_2 {0,p0_capability_set};
-/// thread kobject_3 {&(kobject_2),65716,536887288};
+/// exit_kobject id_1 {};
+/// klog_kobject id_2 {"hello"};
+/// kobject * const id_3[2] {&(id_1), &(id_2)};
+/// process id_4 {0x0, id_3};
+/// thread id_0 {&(id_4), 0x40004ff8, 0x40005000, 0x40007000, 0x40009000, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, {0x0, 0x1e84, 0x0, 0x0}};
+/// thread * const hart_0_threads[1] {&(id_0)};
 /// }
-/// thread * const threads[1] {&(kobject_3)};
+/// hart_state const harts[1] {{hart_0_threads, 0x1, 0x88402000}};
 /// ```
 pub fn generate_cpp(system: &runtypes::Configuration) -> Result<String, Error> {
     let mut id_iter = IdentifierIterator::default();
@@ -255,7 +319,39 @@ pub fn generate_cpp(system: &runtypes::Configuration) -> Result<String, Error> {
         .map(|(p, pid)| process_kobjects(&mut id_iter, pid, p))
         .collect::<Result<Vec<(String, Vec<Statement>)>, Error>>()?;
 
-    let proc_stm: Vec<Statement> = procs.iter().map(|(_, s)| s).flatten().cloned().collect();
+    // The kernel's anonymous memory regions are its per-hart stacks.
+    let stacks = &system.kernel.anon_mem;
+
+    if stacks.len() != usize::try_from(system.harts)? {
+        return Err(format_err!(
+            "The kernel has {} stacks, but the system has {} harts",
+            stacks.len(),
+            system.harts
+        ));
+    }
+
+    let harts: Vec<(Vec<Statement>, Expression)> = (0..system.harts)
+        .map(|hart| {
+            hart_state(
+                hart,
+                &system
+                    .processes
+                    .values()
+                    .zip(procs.iter())
+                    .filter(|(p, _)| p.hart == hart)
+                    .map(|(_, (t, _))| t.as_str())
+                    .collect::<Vec<&str>>(),
+                &stacks[usize::try_from(hart).unwrap()],
+            )
+        })
+        .collect();
+
+    let proc_stm: Vec<Statement> = procs
+        .iter()
+        .flat_map(|(_, s)| s)
+        .chain(harts.iter().flat_map(|(s, _)| s))
+        .cloned()
+        .collect();
 
     Ok([
         Statement::Include {
@@ -268,14 +364,9 @@ pub fn generate_cpp(system: &runtypes::Configuration) -> Result<String, Error> {
             statements: proc_stm,
         },
         Statement::ArrayDefinition {
-            name: "threads".to_string(),
-            r#type: "thread * const".to_string(),
-            init_args: procs
-                .iter()
-                .map(|(t, _)| {
-                    Expression::AddressOf(Box::new(Expression::Identifier(t.to_string())))
-                })
-                .collect(),
+            name: "harts".to_string(),
+            r#type: "hart_state const".to_string(),
+            init_args: harts.into_iter().map(|(_, e)| e).collect(),
         },
     ]
     .iter()
@@ -290,19 +381,37 @@ pub fn generate_cpp(system: &runtypes::Configuration) -> Result<String, Error> {
 ///
 /// ```c++
 /// #pragma once
+/// #include "hart.hpp"
 /// #include "thread.hpp"
-/// extern thread * const threads[1];
+/// constexpr size_t thread_count {0x1};
+/// constexpr size_t hart_count {0x1};
+/// extern hart_state const harts[1];
 /// ```
 pub fn generate_hpp(system: &runtypes::Configuration) -> Result<String, Error> {
     Ok([
         Statement::PragmaOnce,
+        Statement::Include {
+            header: "hart.hpp".to_string(),
+        },
         Statement::Include {
             header: "thread.hpp".to_string(),
         },
+        Statement::VariableDefinition {
+            r#type: "constexpr size_t".to_string(),
+            name: "thread_count".to_string(),
+            init_args: vec![Expression::LiteralUnsigned(
+                system.processes.len().try_into()?,
+            )],
+        },
+        Statement::VariableDefinition {
+            r#type: "constexpr size_t".to_string(),
+            name: "hart_count".to_string(),
+            init_args: vec![Expression::LiteralUnsigned(system.harts)],
+        },
         Statement::ArrayFwdDeclaration {
-            r#type: "thread * const".to_string(),
-            name: "threads".to_string(),
-            count: system.processes.len(),
+            r#type: "hart_state const".to_string(),
+            name: "harts".to_string(),
+            count: system.harts.try_into()?,
         },
     ]
     .iter()
@@ -9,16 +9,24 @@ mod bump_ptr_alloc;
 mod cfgfile;
 mod cfgtypes;
 mod codegen;
+mod config_blob;
 mod constants;
+mod devicetree;
 mod elf;
 mod elf_writer;
 mod epoxy;
+mod fdt;
 mod framebuffer;
+mod hex_writer;
 mod interval;
 mod kernel_codegen;
+mod output;
 mod page_table;
 mod phys_mem;
+mod raw_writer;
 mod runtypes;
+mod schedulability;
+mod uboot_writer;
 mod vec_utils;
 
 fn main() -> Result<(), Error> {
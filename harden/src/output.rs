@@ -0,0 +1,100 @@
+//! Write generated artifacts to files or standard output.
+//!
+//! Files are replaced atomically: We write into a temporary file in the same directory and rename
+//! it over the destination once everything was written. Build systems thus never see a partially
+//! written artifact, even if harden fails halfway through.
+
+use anyhow::{Context, Error};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the temporary file for `path`.
+fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format_err!("Output path {} is not a file", path.display()))?;
+
+    Ok(path.with_file_name(format!(
+        ".{}.tmp{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    )))
+}
+
+/// Write a file atomically with the content that `f` produces.
+pub fn write_file<F>(path: &Path, f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>,
+{
+    let temp = temp_path(path)?;
+
+    let result = File::create(&temp).map_err(Error::from).and_then(|file| {
+        let mut buf = BufWriter::new(file);
+
+        f(&mut buf)?;
+
+        buf.into_inner()?.sync_all()?;
+        fs::rename(&temp, path)?;
+
+        Ok(())
+    });
+
+    if result.is_err() {
+        // The temporary file may not even exist, so there is nothing to do if this fails.
+        let _ = fs::remove_file(&temp);
+    }
+
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write the content that `f` produces to a file or to standard output, if there is no path.
+///
+/// Binary content is not written to a terminal.
+pub fn write<F>(path: Option<&Path>, binary: bool, f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>,
+{
+    match path {
+        Some(path) => write_file(path, f),
+        None if binary && atty::is(atty::Stream::Stdout) => Err(format_err!(
+            "Refusing to write binary data to a terminal. Please redirect output to a stream."
+        )),
+        None => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+
+            f(&mut out)?;
+            out.flush()?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_file() {
+        let dir = std::env::temp_dir().join(format!("harden-output-test-{}", std::process::id()));
+        let path = dir.join("out.bin");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        write_file(&path, |out| Ok(out.write_all(b"first")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+
+        // A failing writer leaves the previous content and no temporary file behind.
+        assert!(write_file(&path, |out| {
+            out.write_all(b"partial")?;
+            Err(format_err!("Failure"))
+        })
+        .is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
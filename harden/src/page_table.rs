@@ -1,6 +1,6 @@
 use anyhow::Error;
 use log::debug;
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 
 use crate::address_space::{AddressSpace, Permissions};
 use crate::interval::Interval;
@@ -16,11 +16,6 @@ pub enum PageTableError {
     /// can be represented.
     PhysAddressNotMappable { pte: u64 },
 
-    /// A page table was allocated at a place where we cannot point to it.
-    ///
-    /// This is in an internal error, because we cannot ask for below-4G memory yet.
-    IllegalPageTablePlacement { paddr: u64 },
-
     /// We failed to allocate backing storage for page tables.
     MemoryAllocationFailed,
 }
@@ -33,12 +28,9 @@ impl std::fmt::Display for PageTableError {
                 "Page table entry {:#x} is not representable in the page table.",
                 pte
             ),
-            PageTableError::IllegalPageTablePlacement { paddr } => write!(
-                f,
-                "Internal error: A page table was allocated at {:#x}, but we cannot use this address.",
-                paddr
-            ),
-            PageTableError::MemoryAllocationFailed => write!(f, "Failed to allocate memory for page table structures."),
+            PageTableError::MemoryAllocationFailed => {
+                write!(f, "Failed to allocate memory for page table structures.")
+            }
         }
     }
 }
@@ -139,7 +131,6 @@ fn page_table(
     addr_space: &AddressSpace,
 ) -> Result<Option<u64>, PageTableError> {
     let pt_data = &(0..(1 << format.bits_per_level))
-        .into_iter()
         .map(|pt_index| -> Interval {
             let size = 1 << u64::from(12 + level * format.bits_per_level);
             let canon_vaddr = canonicalize_vaddr(vaddr + pt_index * size, format);
@@ -186,19 +177,17 @@ fn page_table(
                 vec_u32_to_bytes(&cropped)
             }
 
-            9 => vec_u64_to_bytes(&pt_data),
+            9 => vec_u64_to_bytes(pt_data),
             _ => unimplemented!("Bit per level {} is not handled yet", format.bits_per_level),
         };
         let phys = pmem
-            .place(&combined, PlaceAs::Shareable)
+            .place(&combined, PlaceAs::Shareable, Permissions::read_only())
             .ok_or(PageTableError::MemoryAllocationFailed)?;
 
         assert_eq!(combined.len(), 4096);
         debug!("Allocated page table at phys {:#x}", phys);
 
-        Ok(Some(phys.try_into().map_err(|_| {
-            PageTableError::IllegalPageTablePlacement { paddr: phys }
-        })?))
+        Ok(Some(phys))
     }
 }
 
@@ -211,8 +200,7 @@ pub fn generate(
         Format::RiscvSv32 => {
             let root_pt: u64 =
                 page_table(FORMAT_SV32, pmem, FORMAT_SV32.levels - 1, 0, addr_space)?
-                    .expect("We should have at least one mapping?")
-                    .into();
+                    .expect("We should have at least one mapping?");
 
             // Turn the page table pointer into a valid SATP value for Sv32.
             let satp = (root_pt >> 12) | 1 << 31;
@@ -223,8 +211,7 @@ pub fn generate(
         Format::RiscvSv39 => {
             let root_pt: u64 =
                 page_table(FORMAT_SV39, pmem, FORMAT_SV39.levels - 1, 0, addr_space)?
-                    .expect("We should have at least one mapping?")
-                    .into();
+                    .expect("We should have at least one mapping?");
 
             // Turn the page table pointer into a valid SATP value for Sv39.
             let satp = (root_pt >> 12) | 8 << 60;
@@ -1,11 +1,14 @@
 //! This module implements a memory abstraction. Any content rendered into this memory will be part
 //! of the boot image that is generated.
 
+use anyhow::Error;
+use itertools::Itertools;
 use log::debug;
 use std::collections::HashMap;
 use std::convert::{From, TryInto};
 
 use crate::bump_ptr_alloc::{BumpPointerAlloc, ChainedAlloc, SimpleAlloc};
+use crate::elf::Permissions;
 use crate::interval::Interval;
 
 #[derive(Debug, Copy, Clone)]
@@ -17,10 +20,16 @@ pub enum PlaceAs {
     Shareable,
 }
 
-#[derive(Debug)]
+/// A populated piece of physical memory with uniform permissions.
+#[derive(Debug, PartialEq, Eq)]
 pub struct Chunk {
     pub paddr: u64,
     pub data: Vec<u8>,
+
+    /// The combined permissions of everything that was placed into this memory. Memory that was
+    /// only written, but never placed, is conservatively marked as readable, writable and
+    /// executable.
+    pub perm: Permissions,
 }
 
 impl From<&Chunk> for Interval {
@@ -29,10 +38,23 @@ impl From<&Chunk> for Interval {
     }
 }
 
+/// Data written to memory.
+#[derive(Debug)]
+struct Block {
+    paddr: u64,
+    data: Vec<u8>,
+}
+
+impl From<&Block> for Interval {
+    fn from(block: &Block) -> Self {
+        Interval::new_with_size(block.paddr, block.data.len().try_into().unwrap())
+    }
+}
+
 /// A memory abstraction that allows reading and writing to it.
 #[derive(Default, Debug)]
 struct Memory {
-    pub chunks: Vec<Chunk>,
+    pub blocks: Vec<Block>,
 }
 
 /// A representation of physical memory as it will be written into the boot image.
@@ -47,33 +69,37 @@ pub struct PhysMemory {
     /// TODO: Note that this is extremely primitive and no attempt has been made to optimize for
     /// sharing at smaller granularities than whole ELF segments.
     shareable_memory: HashMap<Vec<u8>, u64>,
+
+    /// The permissions of everything that was placed. Ranges may overlap, if shareable memory is
+    /// placed multiple times.
+    permissions: Vec<(Interval, Permissions)>,
 }
 
 /// A recursive helper function for `Memory::read()`.
 fn read_rec<'a, I>(mut iter: I, pivl: Interval) -> Vec<u8>
 where
-    I: Iterator<Item = &'a Chunk> + Clone,
+    I: Iterator<Item = &'a Block> + Clone,
 {
     if pivl.empty() {
         vec![]
     } else {
         match iter.next() {
             None => vec![0; pivl.size().try_into().unwrap()],
-            Some(chunk) => {
-                let chunk_ivl: Interval = chunk.into();
-                let intersects = pivl.intersects(chunk_ivl);
-                let intersection = pivl.intersection(chunk_ivl);
+            Some(block) => {
+                let block_ivl: Interval = block.into();
+                let intersects = pivl.intersects(block_ivl);
+                let intersection = pivl.intersection(block_ivl);
 
-                if intersects && pivl.from < chunk_ivl.from {
-                    vec![
+                if intersects && pivl.from < block_ivl.from {
+                    [
                         read_rec(
                             iter.clone(),
                             Interval {
                                 from: pivl.from,
-                                to: chunk_ivl.from,
+                                to: block_ivl.from,
                             },
                         ),
-                        chunk
+                        block
                             .data
                             .iter()
                             .take(intersection.size().try_into().unwrap())
@@ -89,11 +115,11 @@ where
                     ]
                     .concat()
                 } else if intersects {
-                    vec![
-                        chunk
+                    [
+                        block
                             .data
                             .iter()
-                            .skip((pivl.from - chunk_ivl.from).try_into().unwrap())
+                            .skip((pivl.from - block_ivl.from).try_into().unwrap())
                             .take(intersection.size().try_into().unwrap())
                             .copied()
                             .collect::<Vec<u8>>(),
@@ -117,7 +143,7 @@ where
 
 impl Memory {
     fn write(&mut self, paddr: u64, data: &[u8]) {
-        self.chunks.push(Chunk {
+        self.blocks.push(Block {
             paddr,
             data: data.to_vec(),
         })
@@ -125,7 +151,7 @@ impl Memory {
 
     fn read(&self, paddr: u64, size: u64) -> Vec<u8> {
         read_rec(
-            self.chunks.iter().rev(),
+            self.blocks.iter().rev(),
             Interval::new_with_size(paddr, size),
         )
     }
@@ -133,12 +159,12 @@ impl Memory {
     /// Simplify the internal representation by combining all previous writes.
     fn flattened(&self) -> Memory {
         let mut all_ivls = self
-            .chunks
+            .blocks
             .iter()
             .map(|c| c.into())
             .collect::<Vec<Interval>>();
 
-        all_ivls.sort_by(|a, b| a.from.cmp(&b.from));
+        all_ivls.sort_by_key(|a| a.from);
 
         // The list of all intervals that contain data.
         let joined_ivls = all_ivls
@@ -160,9 +186,9 @@ impl Memory {
 
         // Just re-read the populated intervals to join all underlying chunks.
         Memory {
-            chunks: joined_ivls
+            blocks: joined_ivls
                 .iter()
-                .map(|i| Chunk {
+                .map(|i| Block {
                     paddr: i.from,
                     data: self.read(i.from, i.size()),
                 })
@@ -171,7 +197,7 @@ impl Memory {
     }
 
     fn size(&self) -> u64 {
-        self.chunks
+        self.blocks
             .iter()
             .map(|c| c.data.len())
             .sum::<usize>()
@@ -185,6 +211,7 @@ impl PhysMemory {
         PhysMemory {
             memory: Memory::default(),
             shareable_memory: HashMap::default(),
+            permissions: vec![],
             free_memory,
         }
     }
@@ -194,26 +221,65 @@ impl PhysMemory {
         self.memory.write(paddr, data)
     }
 
-    pub fn place_unique(&mut self, data: &[u8]) -> Option<u64> {
+    fn add_permissions(&mut self, paddr: u64, size: usize, perm: Permissions) {
+        self.permissions.push((
+            Interval::new_with_size(paddr, size.try_into().unwrap()),
+            perm,
+        ));
+    }
+
+    pub fn place_unique(&mut self, data: &[u8], perm: Permissions) -> Option<u64> {
         let addr = self.free_memory.alloc(data.len().try_into().unwrap())?;
 
         self.write(addr, data);
+        self.add_permissions(addr, data.len(), perm);
         Some(addr)
     }
 
-    pub fn place_shareable(&mut self, data: &[u8]) -> Option<u64> {
-        self.shareable_memory
-            .get(data)
-            .map(|v| {
-                debug!("Reusing {:#x} bytes at {:#x}.", data.len(), *v);
-                *v
-            })
-            .or_else(|| {
-                let newly_written = self.place_unique(data)?;
+    pub fn place_shareable(&mut self, data: &[u8], perm: Permissions) -> Option<u64> {
+        match self.shareable_memory.get(data).copied() {
+            Some(addr) => {
+                debug!("Reusing {:#x} bytes at {:#x}.", data.len(), addr);
+
+                self.add_permissions(addr, data.len(), perm);
+                Some(addr)
+            }
+            None => {
+                let newly_written = self.place_unique(data, perm)?;
 
                 self.shareable_memory.insert(data.to_vec(), newly_written);
                 Some(newly_written)
-            })
+            }
+        }
+    }
+
+    /// Places data at a fixed, page aligned physical address. The memory is taken out of the free
+    /// memory, so this should happen before anything else is placed. Fails if the memory is already
+    /// in use or not available at all.
+    pub fn place_at(&mut self, paddr: u64, data: &[u8], perm: Permissions) -> Result<(), Error> {
+        let ivl = Interval::new_with_size(paddr, data.len().try_into()?);
+
+        if let Some((used, _)) = self.permissions.iter().find(|(p, _)| p.intersects(ivl)) {
+            return Err(format_err!(
+                "Physical memory at {:#x}-{:#x} overlaps memory in use at {:#x}-{:#x}",
+                ivl.from,
+                ivl.to,
+                used.from,
+                used.to
+            ));
+        }
+
+        if !self.free_memory.reserve(ivl) {
+            return Err(format_err!(
+                "Physical memory at {:#x}-{:#x} is not available",
+                ivl.from,
+                ivl.to
+            ));
+        }
+
+        self.write(paddr, data);
+        self.add_permissions(paddr, data.len(), perm);
+        Ok(())
     }
 
     /// Places data at a page aligned and free location in physical memory. Returns the address at
@@ -221,11 +287,12 @@ impl PhysMemory {
     ///
     /// When `ptype` is `PlaceAs::Shareable` memory can be de-duplicated. Placing the same shareable
     /// data twice will result in the same address being returned. This is useful for read-only
-    /// memory to save space.
-    pub fn place(&mut self, data: &[u8], ptype: PlaceAs) -> Option<u64> {
+    /// memory to save space. The permissions of shared memory are the union of the permissions
+    /// it was placed with.
+    pub fn place(&mut self, data: &[u8], ptype: PlaceAs, perm: Permissions) -> Option<u64> {
         match ptype {
-            PlaceAs::Shareable => self.place_shareable(data),
-            PlaceAs::Unique => self.place_unique(data),
+            PlaceAs::Shareable => self.place_shareable(data, perm),
+            PlaceAs::Unique => self.place_unique(data, perm),
         }
     }
 
@@ -236,9 +303,83 @@ impl PhysMemory {
         self.memory.read(paddr, size)
     }
 
-    /// Return a list of memory chunks.
+    /// Split a block of memory into chunks at the boundaries where its permissions change.
+    fn split_by_permissions(&self, block: Block) -> Vec<Chunk> {
+        let block_ivl: Interval = (&block).into();
+        let placed = self
+            .permissions
+            .iter()
+            .filter(|(ivl, _)| ivl.intersects(block_ivl))
+            .collect::<Vec<_>>();
+
+        let mut boundaries = placed
+            .iter()
+            .flat_map(|(ivl, _)| vec![ivl.from, ivl.to])
+            .filter(|&b| block_ivl.contains(b))
+            .chain(std::iter::once(block_ivl.from))
+            .collect::<Vec<u64>>();
+
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        // Each piece between two boundaries is either completely covered by a placement or not at
+        // all.
+        let pieces = boundaries
+            .iter()
+            .zip(
+                boundaries
+                    .iter()
+                    .skip(1)
+                    .chain(std::iter::once(&block_ivl.to)),
+            )
+            .map(|(&from, &to)| {
+                let ivl = Interval { from, to };
+                let perm = placed
+                    .iter()
+                    .filter(|(p, _)| p.intersects(ivl))
+                    .map(|&&(_, perm)| perm)
+                    .fold1(Permissions::union)
+                    .unwrap_or_else(Permissions::all);
+
+                (ivl, perm)
+            });
+
+        // Join neighboring pieces with the same permissions.
+        let joined = pieces.fold(
+            vec![],
+            |mut acc: Vec<(Interval, Permissions)>, (ivl, perm)| {
+                match acc.last_mut() {
+                    Some((last, last_perm)) if *last_perm == perm => last.to = ivl.to,
+                    _ => acc.push((ivl, perm)),
+                }
+
+                acc
+            },
+        );
+
+        joined
+            .into_iter()
+            .map(|(ivl, perm)| {
+                let offset: usize = (ivl.from - block.paddr).try_into().unwrap();
+                let size: usize = ivl.size().try_into().unwrap();
+
+                Chunk {
+                    paddr: ivl.from,
+                    data: block.data[offset..offset + size].to_vec(),
+                    perm,
+                }
+            })
+            .collect()
+    }
+
+    /// Return a list of memory chunks. Each chunk has uniform permissions.
     pub fn chunks(&self) -> Vec<Chunk> {
-        self.memory.flattened().chunks
+        self.memory
+            .flattened()
+            .blocks
+            .into_iter()
+            .flat_map(|b| self.split_by_permissions(b))
+            .collect()
     }
 
     /// Return the amount of memory stored so far.
@@ -274,4 +415,83 @@ mod tests {
         let flattened = m.flattened();
         assert_eq!(flattened.read(0x0fff, 3), vec![7, 8, 2]);
     }
+
+    #[test]
+    fn test_place_at() {
+        let mut pmem = PhysMemory::new(
+            std::iter::once(BumpPointerAlloc::new(
+                Interval::new_with_size(0x1000, 0x4000),
+                0x1000,
+            ))
+            .collect(),
+        );
+
+        pmem.place_at(0x2000, &[1; 0x1000], Permissions::read_only())
+            .unwrap();
+
+        assert!(pmem
+            .place_at(0x2000, &[2; 0x1000], Permissions::read_only())
+            .is_err());
+        assert!(pmem
+            .place_at(0x4000, &[2; 0x2000], Permissions::read_only())
+            .is_err());
+
+        // Other placements go around the fixed one.
+        assert_eq!(
+            pmem.place(&[3; 0x1000], PlaceAs::Unique, Permissions::read_only()),
+            Some(0x1000)
+        );
+        assert_eq!(
+            pmem.place(&[4; 0x1000], PlaceAs::Unique, Permissions::read_only()),
+            Some(0x3000)
+        );
+        assert_eq!(pmem.read(0x2000, 1), vec![1]);
+    }
+
+    #[test]
+    fn test_chunk_permissions() {
+        let read_execute = Permissions {
+            write: false,
+            ..Permissions::all()
+        };
+        let mut pmem = PhysMemory::new(
+            std::iter::once(BumpPointerAlloc::new(
+                Interval::new_with_size(0x1000, 0x10000),
+                0x1000,
+            ))
+            .collect(),
+        );
+
+        let code = pmem
+            .place(&[1; 0x1000], PlaceAs::Shareable, read_execute)
+            .unwrap();
+        let data = pmem
+            .place(&[2; 0x2000], PlaceAs::Unique, Permissions::read_write())
+            .unwrap();
+
+        assert_eq!((code, data), (0x1000, 0x2000));
+
+        // Patches keep the permissions of the memory they are written to.
+        pmem.write(0x2800, &[3; 4]);
+
+        // Reusing shareable memory combines permissions.
+        let more = pmem.place(&[4; 0x1000], PlaceAs::Shareable, Permissions::read_only());
+        let shared = pmem.place(&[4; 0x1000], PlaceAs::Shareable, Permissions::read_write());
+        assert_eq!(more, shared);
+
+        // Memory that is only written is assumed to need all permissions.
+        pmem.write(0x5000, &[5]);
+
+        assert_eq!(
+            pmem.chunks()
+                .iter()
+                .map(|c| (c.paddr, c.data.len(), c.perm))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x1000, 0x1000, read_execute),
+                (0x2000, 0x3000, Permissions::read_write()),
+                (0x5000, 1, Permissions::all()),
+            ]
+        );
+    }
 }
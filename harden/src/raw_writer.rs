@@ -0,0 +1,71 @@
+//! A writer for flat binary boot images.
+//!
+//! A flat binary is just the content of physical memory from the lowest to the highest populated
+//! address. Gaps between chunks are filled with zeroes. The binary carries no information where it
+//! needs to be loaded, so the load address is returned to the caller to report it.
+
+use anyhow::Error;
+use log::warn;
+use std::convert::TryFrom;
+use std::io::Write;
+
+use crate::phys_mem::PhysMemory;
+
+/// Gaps larger than this are probably a configuration mistake and are reported.
+const LARGE_GAP: u64 = 16 << 20;
+
+/// Flatten physical memory into one contiguous blob. Returns the load address and the data.
+pub fn flatten(pmem: &PhysMemory) -> Result<(u64, Vec<u8>), Error> {
+    let chunks = pmem.chunks();
+    let load_addr = chunks
+        .first()
+        .map(|c| c.paddr)
+        .ok_or_else(|| format_err!("Refusing to write an empty binary"))?;
+
+    let mut data = vec![];
+
+    for chunk in &chunks {
+        let pos = load_addr + u64::try_from(data.len())?;
+        let gap = chunk.paddr - pos;
+
+        if gap > LARGE_GAP {
+            warn!(
+                "Filling a gap of {} KiB at {:#x} with zeroes",
+                gap >> 10,
+                pos
+            );
+        }
+
+        data.resize(data.len() + usize::try_from(gap)?, 0);
+        data.extend_from_slice(&chunk.data);
+    }
+
+    Ok((load_addr, data))
+}
+
+/// Write physical memory as a flat binary. Returns the load address of the binary.
+pub fn write<T: Write>(buf: &mut T, pmem: &PhysMemory) -> Result<u64, Error> {
+    let (load_addr, data) = flatten(pmem)?;
+
+    buf.write_all(&data)?;
+    Ok(load_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut pmem = PhysMemory::new(std::iter::empty().collect());
+        let mut out = vec![];
+
+        assert!(write(&mut out, &pmem).is_err());
+
+        pmem.write(0x1004, &[3, 4]);
+        pmem.write(0x1000, &[1, 2]);
+
+        assert_eq!(write(&mut out, &pmem).unwrap(), 0x1000);
+        assert_eq!(out, vec![1, 2, 0, 0, 3, 4]);
+    }
+}
@@ -2,14 +2,26 @@
 //! `cfgtypes`.
 
 use std::collections::BTreeMap;
+use std::convert::TryInto;
 
 use crate::cfgtypes;
+use crate::constants::ARG_REGISTERS;
 use crate::framebuffer;
 
 #[derive(Debug, Clone)]
 pub enum MemoryRegion {
-    AnonymousZeroes { size: u64 },
-    Phys { size: u64, start: u64 },
+    AnonymousZeroes {
+        size: u64,
+    },
+    Phys {
+        size: u64,
+        start: u64,
+    },
+
+    /// Pre-initialized data that is mapped read-only.
+    ReadOnlyData {
+        data: Vec<u8>,
+    },
 }
 
 impl MemoryRegion {
@@ -17,6 +29,7 @@ impl MemoryRegion {
         match self {
             MemoryRegion::AnonymousZeroes { size } => *size,
             MemoryRegion::Phys { size, .. } => *size,
+            MemoryRegion::ReadOnlyData { data } => data.len().try_into().unwrap(),
         }
     }
 }
@@ -60,6 +73,34 @@ pub struct Resource {
     pub opt_region: Option<VirtualMemoryRegion>,
 }
 
+/// A CPU budget that is replenished every period. All values are in timer ticks.
+///
+/// Budgets are advisory. The schedulability analysis relies on them, but the kernel does not
+/// enforce them.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub budget: u64,
+    pub period: u64,
+}
+
+/// Validated scheduling parameters of a thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedParams {
+    pub priority: u8,
+
+    /// The time slice in timer ticks.
+    pub time_slice: u64,
+
+    pub budget: Option<Budget>,
+}
+
+/// A per-instance configuration record and the memory region it is serialized into.
+#[derive(Debug)]
+pub struct InstanceConfig {
+    pub value: cfgtypes::Config,
+    pub region: VirtualMemoryRegion,
+}
+
 pub type ProcessMap = BTreeMap<String, Process>;
 pub type ResourceMap = BTreeMap<String, Resource>;
 
@@ -74,17 +115,44 @@ pub struct Process {
     pub resources: ResourceMap,
 
     /// Additional anonymous memory regions (stack, heap, ...).
+    ///
+    /// For the kernel, these are the kernel stacks of all harts in hart order.
     pub anon_mem: Vec<VirtualMemoryRegion>,
 
+    /// The thread-local storage block of the thread. The thread pointer (tp) initially points to
+    /// its start. The boot image initializes it from the TLS template of the binary.
+    ///
+    /// The kernel has none.
+    pub tls: Option<VirtualMemoryRegion>,
+
+    /// The optional configuration record of this process instance.
+    pub config: Option<InstanceConfig>,
+
     pub stack_ptr: u64,
-    pub heap_start: u64,
-    pub heap_end: u64,
+
+    /// The initial values of the argument registers a0-a7.
+    pub initial_regs: [u64; ARG_REGISTERS],
+
+    pub sched: SchedParams,
+
+    /// The hart the thread of this process is pinned to.
+    pub hart: u64,
+
+    /// The configured load base for a position-independent binary.
+    pub load_base: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct Configuration {
     pub name: String,
     pub available_memory: Vec<cfgtypes::MemoryRegion>,
+
+    /// The devices of the machine as given in the machine description.
+    pub devices: Vec<cfgtypes::NamedResource>,
+
+    /// The number of harts in the system.
+    pub harts: u64,
+
     pub kernel: Process,
     pub processes: ProcessMap,
 }
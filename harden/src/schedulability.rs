@@ -0,0 +1,278 @@
+//! Static schedulability analysis for fixed-priority scheduling.
+//!
+//! Threads with a CPU budget are treated as periodic tasks with an implicit deadline equal to their
+//! period. We perform a classic response-time analysis: The worst-case response time of a task is
+//! its own budget plus the interference of all tasks with the same or higher priority. The kernel
+//! schedules threads of the same priority round-robin, so we conservatively count them as
+//! interference as well.
+//!
+//! Threads without a budget can run forever. They are fine as long as no budgeted thread has to
+//! compete with them.
+//!
+//! The analysis assumes that budgets are enforced, i.e. that a thread never runs for more than its
+//! budget in a period. The kernel does not enforce budgets yet, so the result only holds if all
+//! threads stay within their budgets on their own.
+
+use crate::runtypes::{Budget, SchedParams};
+
+/// Reasons why a set of threads is not schedulable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchedulabilityError {
+    /// A thread without a budget has the same or a higher priority than a budgeted thread.
+    UnboundedInterference { thread: String, interferer: String },
+
+    /// The worst-case response time of a thread exceeds its deadline.
+    DeadlineMiss {
+        thread: String,
+        response_time: u64,
+        deadline: u64,
+    },
+}
+
+impl std::fmt::Display for SchedulabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchedulabilityError::UnboundedInterference { thread, interferer } => write!(
+                f,
+                "Thread {} can be starved by thread {}, which has no budget and at least the same priority.",
+                thread, interferer
+            ),
+            SchedulabilityError::DeadlineMiss {
+                thread,
+                response_time,
+                deadline,
+            } => write!(
+                f,
+                "Thread {} misses its deadline: worst-case response time is at least {} ticks, but its period is {} ticks.",
+                thread, response_time, deadline
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchedulabilityError {}
+
+/// All reasons why a set of threads is not schedulable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unschedulable(pub Vec<SchedulabilityError>);
+
+impl std::fmt::Display for Unschedulable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.0
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        )
+    }
+}
+
+impl std::error::Error for Unschedulable {}
+
+/// The analysis result for a single thread with a budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTime {
+    pub thread: String,
+
+    /// The worst-case response time in timer ticks.
+    pub response_time: u64,
+
+    /// The deadline (the period) in timer ticks.
+    pub deadline: u64,
+}
+
+/// Compute the worst-case response time of a thread with the given budget. `interferers` are all
+/// other threads with the same or a higher priority.
+fn response_time(
+    name: &str,
+    budget: Budget,
+    interferers: &[(&str, SchedParams)],
+) -> Result<ResponseTime, SchedulabilityError> {
+    if let Some((interferer, _)) = interferers.iter().find(|(_, s)| s.budget.is_none()) {
+        return Err(SchedulabilityError::UnboundedInterference {
+            thread: name.to_string(),
+            interferer: interferer.to_string(),
+        });
+    }
+
+    let deadline_miss = |response_time| SchedulabilityError::DeadlineMiss {
+        thread: name.to_string(),
+        response_time,
+        deadline: budget.period,
+    };
+
+    // Iterate until the response time reaches a fixed point or exceeds the deadline. Values that
+    // do not fit into 64 bits are certainly beyond any deadline.
+    let mut response_time = budget.budget;
+
+    loop {
+        let next = interferers
+            .iter()
+            .filter_map(|(_, s)| s.budget)
+            .try_fold(budget.budget, |acc, b| {
+                let activations = response_time.checked_add(b.period - 1)? / b.period;
+
+                acc.checked_add(activations.checked_mul(b.budget)?)
+            })
+            .ok_or_else(|| deadline_miss(u64::MAX))?;
+
+        if next > budget.period {
+            return Err(deadline_miss(next));
+        } else if next == response_time {
+            return Ok(ResponseTime {
+                thread: name.to_string(),
+                response_time,
+                deadline: budget.period,
+            });
+        }
+
+        response_time = next;
+    }
+}
+
+/// Check whether all threads with a budget meet their deadlines, assuming they never exceed their
+/// budget.
+///
+/// On success, this returns the worst-case response time of each budgeted thread. Otherwise, it
+/// returns the reasons for every thread that may miss its deadline.
+pub fn analyze(threads: &[(&str, SchedParams)]) -> Result<Vec<ResponseTime>, Unschedulable> {
+    let mut response_times = vec![];
+    let mut errors = vec![];
+
+    for &(name, sched) in threads {
+        let budget = match sched.budget {
+            Some(budget) => budget,
+            None => continue,
+        };
+
+        let interferers: Vec<(&str, SchedParams)> = threads
+            .iter()
+            .filter(|&&(other, other_sched)| {
+                other != name && other_sched.priority >= sched.priority
+            })
+            .cloned()
+            .collect();
+
+        match response_time(name, budget, &interferers) {
+            Ok(r) => response_times.push(r),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(response_times)
+    } else {
+        Err(Unschedulable(errors))
+    }
+}
+
+/// Return the processor utilization of all budgeted threads in percent.
+pub fn utilization_percent(threads: &[(&str, SchedParams)]) -> u64 {
+    let utilization: f64 = threads
+        .iter()
+        .filter_map(|(_, s)| s.budget)
+        .map(|b| b.budget as f64 / b.period as f64)
+        .sum();
+
+    (utilization * 100.0).ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(priority: u8, budget: u64, period: u64) -> SchedParams {
+        SchedParams {
+            priority,
+            time_slice: 1,
+            budget: Some(Budget { budget, period }),
+        }
+    }
+
+    #[test]
+    fn test_feasible() {
+        // The textbook example: C = (1, 2, 3), T = (4, 6, 13).
+        let result = analyze(&[
+            ("t1", task(3, 1, 4)),
+            ("t2", task(2, 2, 6)),
+            ("t3", task(1, 3, 13)),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            result.iter().map(|r| r.response_time).collect::<Vec<u64>>(),
+            vec![1, 3, 10]
+        );
+    }
+
+    #[test]
+    fn test_deadline_miss() {
+        assert!(matches!(
+            analyze(&[("t1", task(2, 3, 4)), ("t2", task(1, 2, 6))]),
+            Err(Unschedulable(errors)) if matches!(
+                errors.as_slice(),
+                [SchedulabilityError::DeadlineMiss { thread, .. }] if thread == "t2"
+            )
+        ));
+    }
+
+    #[test]
+    fn test_all_deadline_misses() {
+        let errors = analyze(&[
+            ("t1", task(3, 3, 4)),
+            ("t2", task(2, 2, 6)),
+            ("t3", task(1, 2, 8)),
+        ])
+        .unwrap_err()
+        .0;
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| match e {
+                    SchedulabilityError::DeadlineMiss { thread, .. } => thread.as_str(),
+                    _ => panic!("Unexpected error: {}", e),
+                })
+                .collect::<Vec<&str>>(),
+            vec!["t2", "t3"]
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(
+            analyze(&[
+                ("t1", task(2, u64::MAX, u64::MAX)),
+                ("t2", task(1, 1, u64::MAX))
+            ]),
+            Err(Unschedulable(vec![SchedulabilityError::DeadlineMiss {
+                thread: "t2".to_string(),
+                response_time: u64::MAX,
+                deadline: u64::MAX
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_unbounded_interference() {
+        let background = SchedParams {
+            priority: 1,
+            time_slice: 1,
+            budget: None,
+        };
+
+        assert!(analyze(&[("t1", task(2, 1, 4)), ("bg", background)]).is_ok());
+        assert_eq!(
+            analyze(&[("t1", task(1, 1, 4)), ("bg", background)]),
+            Err(Unschedulable(vec![
+                SchedulabilityError::UnboundedInterference {
+                    thread: "t1".to_string(),
+                    interferer: "bg".to_string()
+                }
+            ]))
+        );
+    }
+}
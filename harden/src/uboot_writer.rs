@@ -0,0 +1,242 @@
+//! Writers for boot images that are loaded by U-Boot.
+//!
+//! Both formats wrap the flattened physical memory (see `raw_writer`) together with its load
+//! address and entry point, so U-Boot's `bootm` can load and start the system without further
+//! scripting:
+//!
+//! - A legacy uImage is a 64-byte header in front of the payload [1].
+//! - A FIT image is a device tree that contains the payload as a property [2].
+//!
+//! We don't record timestamps, so the images are reproducible.
+//!
+//! [1] https://github.com/u-boot/u-boot/blob/master/include/image.h
+//! [2] https://github.com/u-boot/u-boot/blob/master/doc/usage/fit/source_file_format.rst
+
+use anyhow::Error;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::convert::TryInto;
+use std::io::Write;
+
+use crate::fdt::{self, Fdt, Node};
+
+const IH_MAGIC: u32 = 0x2705_1956;
+const IH_NMLEN: usize = 32;
+const IH_OS_LINUX: u8 = 5;
+const IH_ARCH_RISCV: u8 = 26;
+const IH_TYPE_KERNEL: u8 = 2;
+const IH_COMP_NONE: u8 = 0;
+
+/// A payload for U-Boot.
+pub struct Image<'a> {
+    /// The name that U-Boot displays for the image.
+    pub name: &'a str,
+    pub load_addr: u64,
+    pub entry: u64,
+    pub data: &'a [u8],
+}
+
+/// Compute the CRC-32 (IEEE 802.3) checksum that U-Boot uses.
+fn crc32(data: &[u8]) -> u32 {
+    let table: Vec<u32> = (0..256)
+        .map(|i| {
+            (0..8).fold(i, |c, _| {
+                if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            })
+        })
+        .collect();
+
+    !data.iter().fold(!0, |crc, &b| {
+        table[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn to_u32(name: &str, value: u64) -> Result<u32, Error> {
+    value
+        .try_into()
+        .map_err(|_| format_err!("The {} {:#x} does not fit into 32 bits", name, value))
+}
+
+fn uimage_header(image: &Image, hcrc: u32) -> Result<Vec<u8>, Error> {
+    let mut header = vec![];
+    let mut name = image.name.as_bytes().to_vec();
+
+    // The name is zero-terminated, if it is shorter than the field.
+    name.resize(IH_NMLEN, 0);
+
+    header.write_u32::<BigEndian>(IH_MAGIC)?;
+    header.write_u32::<BigEndian>(hcrc)?;
+    header.write_u32::<BigEndian>(0)?; // Timestamp
+    header.write_u32::<BigEndian>(image.data.len().try_into()?)?;
+    header.write_u32::<BigEndian>(to_u32("load address", image.load_addr)?)?;
+    header.write_u32::<BigEndian>(to_u32("entry point", image.entry)?)?;
+    header.write_u32::<BigEndian>(crc32(image.data))?;
+    header.write_all(&[IH_OS_LINUX, IH_ARCH_RISCV, IH_TYPE_KERNEL, IH_COMP_NONE])?;
+    header.write_all(&name)?;
+
+    Ok(header)
+}
+
+/// Write a legacy uImage. Addresses need to fit into 32 bits.
+pub fn write_uimage<T: Write>(buf: &mut T, image: &Image) -> Result<(), Error> {
+    // The header checksum is computed with the checksum field set to zero.
+    let hcrc = crc32(&uimage_header(image, 0)?);
+
+    buf.write_all(&uimage_header(image, hcrc)?)?;
+    buf.write_all(image.data)?;
+
+    Ok(())
+}
+
+/// Encode an address with the given number of cells.
+fn address(address_cells: u32, value: u64) -> Vec<u8> {
+    if address_cells == 1 {
+        fdt::cells(&[value as u32])
+    } else {
+        fdt::cells(&[(value >> 32) as u32, value as u32])
+    }
+}
+
+/// Build the device tree of a FIT image.
+fn fit(image: &Image) -> Fdt {
+    // Older U-Boot versions only understand single-cell addresses, so we only use two cells if
+    // we have to.
+    let address_cells = if image.load_addr.max(image.entry) > u32::MAX.into() {
+        2
+    } else {
+        1
+    };
+
+    let kernel = Node::new("kernel")
+        .with_property("description", fdt::strings(&[image.name]))
+        .with_property("data", image.data.to_vec())
+        .with_property("type", fdt::strings(&["kernel"]))
+        .with_property("arch", fdt::strings(&["riscv"]))
+        .with_property("os", fdt::strings(&["linux"]))
+        .with_property("compression", fdt::strings(&["none"]))
+        .with_property("load", address(address_cells, image.load_addr))
+        .with_property("entry", address(address_cells, image.entry))
+        .with_child(
+            Node::new("hash-1")
+                .with_property("value", fdt::cells(&[crc32(image.data)]))
+                .with_property("algo", fdt::strings(&["crc32"])),
+        );
+
+    let configuration = Node::new("conf-1")
+        .with_property("description", fdt::strings(&[image.name]))
+        .with_property("kernel", fdt::strings(&["kernel"]));
+
+    Fdt {
+        boot_cpuid: 0,
+        reserved: vec![],
+        root: Node::new("")
+            .with_property("description", fdt::strings(&[image.name]))
+            .with_property("#address-cells", fdt::cells(&[address_cells]))
+            .with_child(Node::new("images").with_child(kernel))
+            .with_child(
+                Node::new("configurations")
+                    .with_property("default", fdt::strings(&["conf-1"]))
+                    .with_child(configuration),
+            ),
+    }
+}
+
+/// Write a FIT image with a single kernel image and configuration.
+pub fn write_fit<T: Write>(buf: &mut T, image: &Image) -> Result<(), Error> {
+    buf.write_all(&fit(image).to_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{BigEndian, ByteOrder};
+
+    fn image(data: &[u8]) -> Image<'_> {
+        Image {
+            name: "test",
+            load_addr: 0x8000_0000,
+            entry: 0x8000_0100,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_uimage() {
+        let mut out = vec![];
+
+        write_uimage(&mut out, &image(b"123456789")).unwrap();
+
+        let word = |i: usize| BigEndian::read_u32(&out[4 * i..]);
+        let mut header = out[..64].to_vec();
+
+        header[4..8].copy_from_slice(&[0; 4]);
+
+        assert_eq!(out.len(), 64 + 9);
+        assert_eq!(word(0), IH_MAGIC);
+        assert_eq!(word(1), crc32(&header));
+        assert_eq!(word(3), 9);
+        assert_eq!(word(4), 0x8000_0000);
+        assert_eq!(word(5), 0x8000_0100);
+        assert_eq!(word(6), 0xcbf4_3926);
+        assert_eq!(&out[32..36], b"test");
+        assert_eq!(&out[64..], b"123456789");
+
+        assert!(write_uimage(
+            &mut out,
+            &Image {
+                load_addr: 0x1_0000_0000,
+                ..image(b"")
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut out = vec![];
+
+        write_fit(&mut out, &image(b"123456789")).unwrap();
+
+        let fit = Fdt::parse(&out).unwrap();
+        let kernel = fit.find("/images/kernel").unwrap();
+
+        assert_eq!(kernel.property("data"), Some(&b"123456789"[..]));
+        assert_eq!(kernel.u32("load"), Some(0x8000_0000));
+        assert_eq!(kernel.u32("entry"), Some(0x8000_0100));
+        assert_eq!(
+            fit.find("/images/kernel/hash-1")
+                .and_then(|h| h.u32("value")),
+            Some(0xcbf4_3926)
+        );
+        assert_eq!(
+            fit.find("/configurations")
+                .and_then(|c| c.string("default")),
+            Some("conf-1")
+        );
+
+        // 64-bit addresses need two cells.
+        let high = fit_tree(0x1_0000_0000);
+        assert_eq!(
+            high.find("/images/kernel").and_then(|k| k.u64("load")),
+            Some(0x1_0000_0000)
+        );
+    }
+
+    fn fit_tree(load_addr: u64) -> Fdt {
+        fit(&Image {
+            load_addr,
+            ..image(b"")
+        })
+    }
+}
@@ -36,10 +36,10 @@ rec {
           hardenCmd = "${new-harden}/bin/harden -r ${../config} -s ${system} -vvvv";
 
           mkResourceHeader = procName: pkgs.runCommandNoCC "${system}-${procName}-resources.hpp" { }
-            "${hardenCmd} configure-process ${procName} > $out";
+            "${hardenCmd} configure-process ${procName} -o $out";
 
           mkBootImage = user-binaries: pkgs.runCommandNoCC "${system}-boot-image" { } ''
-            ${hardenCmd} boot-image ${user-binaries} > $out
+            ${hardenCmd} boot-image ${user-binaries} -o $out
           '';
 
           # A list of name/program sets that describe all processes that we need to build
@@ -51,7 +51,7 @@ rec {
           ''));
 
           # Return a derivation for the process from the system description.
-          buildProcess = {name, program}: crossPkgs.pkgs.callPackage (./epoxy- + "${program}.nix") {
+          buildProcess = {name, program, ...}: crossPkgs.pkgs.callPackage (./epoxy- + "${program}.nix") {
             resourceHeader = mkResourceHeader name;
             outputName = name;
           };
@@ -60,9 +60,8 @@ rec {
         rec {
           kern-state = pkgs.runCommandNoCC "${system}-kern-state" { } ''
             mkdir -p $out
-            ${hardenCmd} configure-kernel state-hpp > $out/state.hpp
-            ${hardenCmd} configure-kernel state-cpp > $out/state.cpp
-            ${hardenCmd} configure-kernel resources > $out/resources.hpp
+            ${hardenCmd} configure-kernel state-hpp state-cpp resources \
+              -o $out/state.hpp -o $out/state.cpp -o $out/resources.hpp
           '';
 
           kern = crossPkgs.pkgs.callPackage ./epoxy-kern.nix {
@@ -14,9 +14,6 @@ extern "C" char asm_exc_entry[];
 // Restores the given state and executes an sret.
 extern "C" [[noreturn]] void asm_exc_ret(exception_frame const *frame);
 
-// The initial kernel stack pointer.
-extern "C" char kern_stack_end[];
-
 // Entrypoint for interrupts/exceptions from userspace.
 extern "C" [[noreturn]] void user_exc_entry(exception_frame *frame);
 
@@ -10,3 +10,14 @@ struct capability_set {
 };
 
 using processor_id_t = int;
+
+// Scheduling parameters of a thread. All times are in timer ticks.
+struct sched_params {
+  uint8_t priority;
+  uint64_t time_slice;
+
+  // The CPU budget per period. A period of zero means the thread has no
+  // budget. Budgets are advisory and not enforced by the scheduler yet.
+  uint64_t budget;
+  uint64_t period;
+};
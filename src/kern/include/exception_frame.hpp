@@ -39,11 +39,21 @@ public:
   mword_t a4() const { return regs_[14]; }
 
 protected:
-  explicit constexpr exception_frame(mword_t pc, mword_t sp, mword_t a0, mword_t a1) : pc_ {pc}
+  explicit constexpr exception_frame(mword_t pc, mword_t sp, mword_t tp, mword_t a0, mword_t a1,
+                                     mword_t a2, mword_t a3, mword_t a4, mword_t a5, mword_t a6,
+                                     mword_t a7)
+      : pc_ {pc}
   {
     regs_[2] = sp;
+    regs_[4] = tp;
     regs_[10] = a0;
     regs_[11] = a1;
+    regs_[12] = a2;
+    regs_[13] = a3;
+    regs_[14] = a4;
+    regs_[15] = a5;
+    regs_[16] = a6;
+    regs_[17] = a7;
   }
 };
 
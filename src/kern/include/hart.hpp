@@ -0,0 +1,26 @@
+#pragma once
+
+// The offset of hart_state::stack_end for use from assembly.
+#define HART_STATE_STACK_END (2 * __SIZEOF_POINTER__)
+
+#ifndef __ASSEMBLER__
+
+#include <epoxy-api/c_types.hpp>
+
+class thread;
+
+// The per-hart kernel state. The harts array is generated by
+// epoxy-harden (see state.hpp).
+struct hart_state {
+  // The threads pinned to this hart. This is nullptr for idle harts.
+  thread *const *threads;
+  size_t thread_count;
+
+  // The initial kernel stack pointer. The stack is allocated by
+  // epoxy-harden and surrounded by unmapped guard pages.
+  mword_t stack_end;
+};
+
+static_assert(offsetof(hart_state, stack_end) == HART_STATE_STACK_END);
+
+#endif
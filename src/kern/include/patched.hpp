@@ -1,9 +1,12 @@
 #pragma once
 
-#include <epoxy-api/c_types.hpp>
+#include <epoxy-api/types.hpp>
 
-// These will always be 64-bit values to simplify the implementation
-// in epoxy-harden. See patched.S for their definitions.
+// These are native machine words. epoxy-harden serializes them according
+// to the ELF class of the kernel. See patched.S for their definitions.
 
-extern "C" uint64_t const USER_SATPS[];
-extern "C" uint64_t const USER_PCS[];
+extern "C" mword_t const USER_SATPS[];
+extern "C" mword_t const USER_PCS[];
+
+// The physical address of the device tree or zero, if there is none.
+extern "C" mword_t const BOOT_DTB;
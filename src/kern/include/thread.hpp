@@ -2,6 +2,7 @@
 
 #include <epoxy-api/api.hpp>
 
+#include "config_types.hpp"
 #include "exception_frame.hpp"
 
 class process;
@@ -18,6 +19,8 @@ class thread : private exception_frame
 
   thread_state state_;
 
+  sched_params const sched_;
+
   // Exit to userspace via SRET.
   [[noreturn]] void exit_from_preemption();
 
@@ -29,6 +32,10 @@ public:
 
   bool is_runnable() const { return state_ == thread_state::RUNNABLE; }
 
+  // The scheduler only uses the priority and time slice. The budget is
+  // advisory.
+  sched_params const &sched() const { return sched_; }
+
   void exit() { state_ = thread_state::EXITED; }
   void block() { state_ = thread_state::BLOCKED; }
   void unblock() { state_ = thread_state::RUNNABLE; }
@@ -41,5 +48,9 @@ public:
 
   [[noreturn]] void activate();
 
-  thread(process *process, mword_t sp, mword_t a0, mword_t a1);
+  // The initial stack and thread pointers, argument registers (a0-a7) and scheduling parameters
+  // are generated by epoxy-harden. The thread pointer (tp) points to the thread-local storage
+  // block.
+  thread(process *process, mword_t sp, mword_t tp, mword_t a0, mword_t a1, mword_t a2, mword_t a3,
+         mword_t a4, mword_t a5, mword_t a6, mword_t a7, sched_params const &sched);
 };
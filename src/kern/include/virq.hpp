@@ -16,7 +16,7 @@ class virq
 
   /// The list of all threads that are currently blocked on this event
   /// source.
-  vector<thread *, array_size(threads)> blocked_threads_;
+  vector<thread *, thread_count> blocked_threads_;
 
 public:
   /// A default constructed vIRQ that doesn't connect to a real interrupt source.
@@ -1,6 +1,7 @@
 #include "asm.hpp"
 
 #include "csr.hpp"
+#include "state.hpp"
 
 void reset_stack_and_wait_for_interrupt()
 {
@@ -9,7 +10,7 @@ void reset_stack_and_wait_for_interrupt()
       "csrrs zero, sstatus, %1\n"
       "wfi"
       :
-      : "r"(kern_stack_end), "r"(SSTATUS_SIE)
+      : "r"(harts[0].stack_end), "r"(SSTATUS_SIE)
       : "memory");
 
   // We always return directly to userspace after an interrupt in the
@@ -43,7 +43,7 @@ syscall_result_t klog_kobject::invoke(thread *, syscall_args const &args)
 
 syscall_result_t exit_kobject::invoke(thread *thread, [[maybe_unused]] syscall_args const &args)
 {
-  static size_t running_threads {array_size(threads)};
+  static size_t running_threads {thread_count};
 
   format(">> Thread of process ", thread->get_process()->pid(), " is done.\n");
   thread->exit();
@@ -21,10 +21,10 @@ void process::activate()
   if (active_ != this) {
     active_ = this;
 
-    uint64_t const satp {USER_SATPS[pid()]};
-    assert(satp != 0 and static_cast<mword_t>(satp) == satp);
+    mword_t const satp {USER_SATPS[pid()]};
+    assert(satp != 0);
 
-    csr_w<csr::SATP>(static_cast<mword_t>(satp));
+    csr_w<csr::SATP>(satp);
 
     // TODO We could optimize this by using ASIDs.
     asm volatile("sfence.vma" ::: "memory");
@@ -11,34 +11,57 @@
 
 namespace
 {
-constexpr uint64_t schedule_hz {128};
-constexpr uint64_t time_slice_ticks {sbitimer_freq_hz / schedule_hz};
+// Secondary harts are not brought up yet, so everything runs on the
+// boot hart.
+static_assert(hart_count == 1, "SMP is not supported by the kernel yet");
+
+hart_state const &boot_hart {harts[0]};
+
+// Return the highest priority of all runnable threads or -1, if there is none.
+int highest_runnable_priority()
+{
+  int highest {-1};
+
+  for (size_t i = 0; i < boot_hart.thread_count; i++) {
+    thread *const t {boot_hart.threads[i]};
+
+    if (t->is_runnable() and t->sched().priority > highest) {
+      highest = t->sched().priority;
+    }
+  }
+
+  return highest;
+}
 }  // namespace
 
-// We implement a trivial round-robin scheduling for now. This saves us from
-// having a run queue or any other fancy data structure.
+// We implement fixed-priority scheduling with round-robin between threads of
+// the same priority. There are few enough threads that we can afford to scan
+// all of them instead of having a run queue.
 void schedule()
 {
-  while (true) {
-    static_assert(array_size(threads) > 0);
+  // epoxy-harden refuses configurations where the boot hart is idle.
+  assert(boot_hart.thread_count > 0);
 
+  while (true) {
     using thread_list_entry = thread *const;
 
     // Pointing to the last-but-one thread is mostly cosmetical to
-    // ensure we schedule thread[0] initially.
-    static thread_list_entry *thread_cur {&threads[array_size(threads) - 1]};
-    static thread_list_entry *const thread_end {&threads[array_size(threads)]};
+    // ensure we schedule the first thread initially.
+    static thread_list_entry *thread_cur {&boot_hart.threads[boot_hart.thread_count - 1]};
+    static thread_list_entry *const thread_end {&boot_hart.threads[boot_hart.thread_count]};
+
+    int const priority {highest_runnable_priority()};
 
-    for (size_t i = 0; i < array_size(threads); i++) {
+    for (size_t i = 0; priority >= 0 and i < boot_hart.thread_count; i++) {
       if (++thread_cur == thread_end) {
-        thread_cur = &threads[0];
+        thread_cur = &boot_hart.threads[0];
       }
 
       auto const candidate {*thread_cur};
 
-      if (candidate->is_runnable()) {
+      if (candidate->is_runnable() and candidate->sched().priority == priority) {
         csr_rs<csr::SIE>(SIE_STIE);
-        sbi_set_timer(rdtime() + time_slice_ticks);
+        sbi_set_timer(rdtime() + candidate->sched().time_slice);
         candidate->activate();
       }
     }
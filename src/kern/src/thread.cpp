@@ -26,10 +26,13 @@ void clear_lrsc_reservation()
 
 }  // namespace
 
-thread::thread(process *process, mword_t sp, mword_t a0, mword_t a1)
-    : exception_frame {static_cast<mword_t>(USER_PCS[process->pid()]), sp, a0, a1},
+thread::thread(process *process, mword_t sp, mword_t tp, mword_t a0, mword_t a1, mword_t a2,
+               mword_t a3, mword_t a4, mword_t a5, mword_t a6, mword_t a7,
+               sched_params const &sched)
+    : exception_frame {USER_PCS[process->pid()], sp, tp, a0, a1, a2, a3, a4, a5, a6, a7},
       process_ {process},
-      state_ {thread_state::RUNNABLE}
+      state_ {thread_state::RUNNABLE},
+      sched_ {sched}
 {
 }
 